@@ -0,0 +1,64 @@
+//! Re-running Pre-parsed Programs
+//!
+//! Compares evaluating a script from source each time against parsing
+//! it once and evaluating the parsed program repeatedly. Run with
+//! `cargo bench`.
+
+use formula_one::{parse, Interpreter};
+
+use std::time::{Duration, Instant};
+
+/// A script which does a reasonable amount of work for its size
+const SCRIPT: &str = "
+; Sum the first `n` numbers the long way round
+(define sum-to (lambda (n acc)
+    (if (= n 0)
+        acc
+        (sum-to (- n 1) (+ acc n)))))
+(define squares (lambda (n)
+    (if (= n 0)
+        '()
+        (cons (* n n) (squares (- n 1))))))
+(length (squares 20))
+(sum-to 100 0)
+";
+
+/// Number of times each benchmark runs the script
+const ITERATIONS: u32 = 2_000;
+
+/// Time `ITERATIONS` runs of `f`, returning the mean time per run
+fn bench(name: &str, mut f: impl FnMut()) -> Duration {
+    // Warm up before timing anything
+    for _ in 0..(ITERATIONS / 10) {
+        f();
+    }
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let per_run = start.elapsed() / ITERATIONS;
+    println!("{:<24} {:>10.2?} per run", name, per_run);
+    per_run
+}
+
+fn main() {
+    let mut interpreter = Interpreter::new();
+    let parse_only = bench("parse", || {
+        parse(SCRIPT).unwrap();
+    });
+    let from_source = bench("parse and eval", || {
+        interpreter.eval_str(SCRIPT).unwrap();
+    });
+    let program = parse(SCRIPT).unwrap();
+    let pre_parsed = bench("eval pre-parsed", || {
+        interpreter.eval(&program).unwrap();
+    });
+
+    // Evaluating the parsed program never goes back to the source, so
+    // the difference should be roughly the cost of a parse.
+    println!(
+        "re-running saves {:.2?} per run, parsing takes {:.2?}",
+        from_source.saturating_sub(pre_parsed),
+        parse_only
+    );
+}
@@ -7,7 +7,11 @@
 //! The LISP we have to parse is fairly simplified. Token wise we only have:
 //!
 //!  * `(` and `)` - puncutation
+//!  * `'` - quote abbreviation
 //!  * `[0-9]+` - number literals
+//!  * `#t` and `#f` - boolean literals
+//!  * `"..."` - string literals, with `\n`, `\t`, `\"`, `\\` and
+//!    `\u{XXXX}` escapes
 //!  * Everything else is a symbol
 //!
 //! Tokens do however contain a list of leading and trailing trivia
@@ -17,61 +21,797 @@
 //!
 //!  * `<symbol>` - reference to the variable `<symbol>`
 //!  * `<number>` - reference to a numeric literal
-//!  * `(if <cond> <then> <else>)` - condition expression.
-//!  * `(define <symbol> <expr>)` - defines a variable to a given
-//!                                 value
-//!  * `(<symbol> <arg>...)` - Procedure call to `<symbol>`
+//!  * `<string>` - reference to a string literal
+//!  * `<boolean>` - reference to a boolean literal
+//!  * `(if <cond> <then> <else>)` - condition expression. The
+//!    `<else>` expression can be left out, in which case a false
+//!    condition gives `nil`.
+//!  * `(cond (<test> <expr>...)... (else <expr>...))` - evaluates the
+//!    expressions of the first clause whose test is true. A clause
+//!    written `(<test> => <expr>)` calls the function `<expr>` with
+//!    the value of the test instead.
+//!  * `(case <expr> ((<datum>...) <expr>...)... (else <expr>...))` -
+//!    evaluates the expressions of the first clause which lists the
+//!    value of the key expression
+//!  * `(when <cond> <expr>...)` and `(unless <cond> <expr>...)` -
+//!    evaluate the expressions only if the condition is true, or false
+//!  * `(and <expr>...)` and `(or <expr>...)` - short-circuiting
+//!    logical operators
+//!  * `(quote <datum>)` or `'<datum>` - the datum as a literal value
+//!    rather than code to evaluate
+//!  * `(define <symbol> <expr>)` - defines a new variable in the
+//!    current scope with the given value
+//!  * `(set! <symbol> <expr>)` - changes the value of an existing
+//!    variable
+//!  * `(lambda (<symbol>...) <body>...)` - anonymous function which
+//!    closes over the environment it is defined in
+//!  * `(let ((<symbol> <expr>)...) <body>...)` - binds each symbol
+//!    to the value of its expression in a new scope, then evaluates
+//!    the body. `let*` evaluates each expression in the scope of the
+//!    bindings before it, and `letrec` in the scope of every binding
+//!    so functions can refer to each other. `letrec*` is an alias of
+//!    `letrec`, and both evaluate their expressions in order.
+//!  * `(let <name> ((<symbol> <expr>)...) <body>...)` - named let,
+//!    which binds `<name>` to a function of the symbols and calls it
+//!    with the values of the expressions, for writing loops
+//!  * `(define-syntax <symbol> (syntax-rules (<literal>...)
+//!    (<pattern> <template>)...))` - defines a macro. Uses of macros
+//!    are expanded as they are parsed.
+//!  * `(defmacro <symbol> (<symbol>...) <body>...)` - defines a
+//!    macro as a function which is given the data of a use's
+//!    arguments and returns the code to replace it with
+//!  * `(module <name> (export <symbol>...) <body>...)` - a module
+//!    which evaluates its body in its own scope and exports some of
+//!    the bindings it makes
+//!  * `(import <name> <option>...)` - binds the exports of a module,
+//!    optionally adding a `(prefix <symbol>)` to their names or
+//!    giving them new names with `(rename (<from> <to>)...)`
+//!  * `(<fn> <arg>...)` - Procedure call to the function `<fn>`
+//!
+//! A whole source file is a `Program`, which is a sequence of these
+//! forms evaluated in order.
 
 use codespan::*;
+use std::fmt;
+use std::rc::Rc;
 
 /// A single lexical token in the source text
 ///
 /// Each token represents a single logocal item in the source text. A
-/// token is made up of four things:
+/// token is made up of five things:
 ///
 ///  * `kind` - the type of token
 ///  * `span` - the location of the token in the text
+///  * `text` - the source text of the token
 ///  * `leading_triva` - the token trivia immediately before this token
-///  * `trailing_trivia` - the trivia after this token to the end of line
-#[derive(Debug, PartialEq)]
+///  * `trailing_trivia` - the trivia after this token to the end of
+///    line, including the line ending itself
+#[derive(Debug, PartialEq, Clone)]
 pub struct Token {
+    /// The type of token
     pub kind: TokenKind,
     span: Span,
+    text: String,
+    leading_trivia: Vec<Trivia>,
+    trailing_trivia: Vec<Trivia>,
 }
 
-/// Datum for the four kinds of token
-#[derive(Debug, PartialEq)]
+/// Datum for the kinds of token
+#[derive(Debug, PartialEq, Clone)]
 pub enum TokenKind {
     /// The token is the `(` bracket
     LeftBracket,
     /// The token is the `)` bracket
     RightBracket,
+    /// The token is the `'` quote abbreviation
+    Quote,
     /// The token is a numeric literal
     Number(i64),
+    /// The token is a boolean literal
+    Bool(bool),
     /// The token is an unnamed symbol
     Symbol(String),
+    /// The token is a string literal. This holds the value of the
+    /// string after any escapes have been processed.
+    String(String),
+    /// The token is not recognised by the tokeniser
+    Error,
+    /// The end of the source text
+    EndOfFile,
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokenKind::LeftBracket => write!(out, "`(`"),
+            TokenKind::RightBracket => write!(out, "`)`"),
+            TokenKind::Quote => write!(out, "`'`"),
+            TokenKind::Number(n) => write!(out, "number `{}`", n),
+            TokenKind::Bool(b) => write!(out, "boolean `{}`", if *b { "#t" } else { "#f" }),
+            TokenKind::Symbol(s) => write!(out, "symbol `{}`", s),
+            TokenKind::String(s) => write!(out, "string {}", escape(s)),
+            TokenKind::Error => write!(out, "invalid token"),
+            TokenKind::EndOfFile => write!(out, "end of file"),
+        }
+    }
 }
 
 impl Token {
     /// Create a token with the given `kind` and `span`
+    ///
+    /// The text of the token is the canonical spelling of `kind`.
     pub fn with_span(kind: TokenKind, span: Span) -> Self {
-        Token { kind, span }
+        let text = match &kind {
+            TokenKind::LeftBracket => "(".into(),
+            TokenKind::RightBracket => ")".into(),
+            TokenKind::Quote => "'".into(),
+            TokenKind::Number(n) => n.to_string(),
+            TokenKind::Bool(b) => if *b { "#t" } else { "#f" }.into(),
+            TokenKind::Symbol(s) => s.clone(),
+            TokenKind::String(s) => escape(s),
+            TokenKind::Error | TokenKind::EndOfFile => String::new(),
+        };
+        Token::with_text(kind, span, text)
+    }
+
+    /// Create a token with the given `kind` and `span` from the
+    /// source `text` it was read from
+    pub fn with_text(kind: TokenKind, span: Span, text: impl Into<String>) -> Self {
+        Token {
+            kind,
+            span,
+            text: text.into(),
+            leading_trivia: Vec::new(),
+            trailing_trivia: Vec::new(),
+        }
+    }
+
+    /// Append `trivia` to the trivia before this token
+    pub fn with_leading_trivia(mut self, trivia: Vec<Trivia>) -> Self {
+        self.leading_trivia.extend(trivia);
+        self
+    }
+
+    /// Append `trivia` to the trivia after this token
+    pub fn with_trailing_trivia(mut self, trivia: Vec<Trivia>) -> Self {
+        self.trailing_trivia.extend(trivia);
+        self
+    }
+
+    /// Get the location of the token in the source text
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// Get the source text of the token, excluding any trivia
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Get the trivia immediately before this token
+    pub fn leading_trivia(&self) -> &[Trivia] {
+        &self.leading_trivia
+    }
+
+    /// Get the trivia after this token up to the end of the line
+    pub fn trailing_trivia(&self) -> &[Trivia] {
+        &self.trailing_trivia
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        for trivia in self.leading_trivia.iter() {
+            write!(out, "{}", trivia.text)?;
+        }
+        write!(out, "{}", self.text)?;
+        for trivia in self.trailing_trivia.iter() {
+            write!(out, "{}", trivia.text)?;
+        }
+        Ok(())
+    }
+}
+
+/// Escape a string value as a string literal
+///
+/// This is the inverse of the escape processing done by the
+/// tokeniser, and produces the canonical quoted form of `value`.
+pub fn escape(value: &str) -> String {
+    let mut literal = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '\n' => literal.push_str("\\n"),
+            '\t' => literal.push_str("\\t"),
+            '"' => literal.push_str("\\\""),
+            '\\' => literal.push_str("\\\\"),
+            c if c.is_control() => literal.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => literal.push(c),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+/// The kinds of trivia
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum TriviaKind {
+    /// A run of whitespace characters
+    Whitespace,
+    /// A single line comment, not including the line ending
+    Comment,
+}
+
+/// A piece of trivia attached to a token
+///
+/// Trivia is the part of the source text which has no meaning to the
+/// language. It is kept so that a syntax tree can be printed back to
+/// exactly the source it was parsed from.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Trivia {
+    /// The type of trivia
+    pub kind: TriviaKind,
+    /// The location of the trivia in the source text
+    pub span: Span,
+    /// The source text of the trivia
+    pub text: String,
+}
+
+impl Trivia {
+    /// Create a piece of trivia with the given `kind`, `span`, and
+    /// source `text`
+    pub fn new(kind: TriviaKind, span: Span, text: impl Into<String>) -> Self {
+        Trivia {
+            kind,
+            span,
+            text: text.into(),
+        }
     }
 }
 
 /// Syntax expression enum
 ///
 /// Represnts one of the expression forms in the lanauge.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Expr {
     /// A direct reference to a variable symbol
     Symbol(Token, String),
     /// A numeric literal
     Number(Token, i64),
-    /// A conditional expression
-    If(Token, Token, Box<Expr>, Box<Expr>, Box<Expr>, Token),
+    /// A string literal
+    String(Token, String),
+    /// A boolean literal
+    Bool(Token, bool),
+    /// A conditional expression, with an optional `else` branch
+    If(Token, Token, Box<Expr>, Box<Expr>, Option<Box<Expr>>, Token),
+    /// A `cond` form, with its clauses
+    Cond(Token, Token, Vec<CondClause>, Token),
+    /// A `case` form, with its key expression and clauses
+    Case(Token, Token, Box<Expr>, Vec<CaseClause>, Token),
+    /// A `when` form, with its condition and body
+    When(Token, Token, Box<Expr>, Vec<Expr>, Token),
+    /// An `unless` form, with its condition and body
+    Unless(Token, Token, Box<Expr>, Vec<Expr>, Token),
     /// A variable declaration
     Define(Token, Token, Token, Box<Expr>, Token),
+    /// An assignment to an existing variable
+    Set(Token, Token, Token, Box<Expr>, Token),
+    /// An anonymous function, with its parameter list and body
+    /// The body is shared with any closures created from the
+    /// expression.
+    Lambda(Token, Token, Token, Vec<Token>, Token, Rc<[Expr]>, Token),
+    /// A `let` form, with its bindings and body
+    Let(Token, Token, Box<LetBindings>, Vec<Expr>, Token),
+    /// A `let*` form, in which each binding is in scope for the
+    /// expressions of the bindings after it
+    LetStar(Token, Token, Box<LetBindings>, Vec<Expr>, Token),
+    /// A `letrec` or `letrec*` form, in which every binding is in
+    /// scope for the expressions of all of them. The two are the same,
+    /// and the expressions are evaluated and bound in order.
+    Letrec(Token, Token, Box<LetBindings>, Vec<Expr>, Token),
+    /// A named `let` loop, with the name of its function, its bindings
+    /// and body. The body is shared with the closure which runs the
+    /// loop.
+    NamedLet(Token, Token, Token, Box<LetBindings>, Rc<[Expr]>, Token),
+    /// A macro definition, with the `syntax-rules` specification of
+    /// the macro as a datum
+    DefineSyntax(Token, Token, Token, Box<Datum>, Token),
+    /// A procedural macro definition, with its name, parameter list
+    /// and the body of the function which expands it
+    DefMacro(
+        Token,
+        Token,
+        Token,
+        Token,
+        Vec<Token>,
+        Token,
+        Rc<[Expr]>,
+        Token,
+    ),
+    /// A module definition, with its name, export list and body
+    Module(Token, Token, Token, Box<Exports>, Vec<Expr>, Token),
+    /// An import of a module's exports, with the options which change
+    /// the names they are bound to
+    Import(Token, Token, Token, Vec<ImportOption>, Token),
+    /// A quoted datum, written as `(quote <datum>)`
+    Quote(Token, Token, Box<Datum>, Token),
+    /// A quoted datum, written with the `'<datum>` abbreviation
+    QuoteAbbrev(Token, Box<Datum>),
+    /// A short-circuiting logical and of the given expressions
+    And(Token, Token, Vec<Expr>, Token),
+    /// A short-circuiting logical or of the given expressions
+    Or(Token, Token, Vec<Expr>, Token),
     /// A funciton call expression
-    Call(Token, Token, Vec<Expr>, Token),
+    Call(Token, Box<Expr>, Vec<Expr>, Token),
+    /// The expression a use of a macro expanded into, along with the
+    /// location of the use and the name of the macro
+    Expansion(Span, String, Box<Expr>),
+}
+
+impl Expr {
+    /// Get the location of the expression in the source text
+    ///
+    /// For a bracketed form this covers everything from the opening
+    /// bracket to the closing one.
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Symbol(token, _) | Expr::Number(token, _) | Expr::String(token, _) => {
+                token.span()
+            }
+            Expr::Bool(token, _) => token.span(),
+            Expr::QuoteAbbrev(quote, datum) => quote.span().merge(datum.span()),
+            Expr::Quote(open, .., close)
+            | Expr::If(open, .., close)
+            | Expr::Cond(open, .., close)
+            | Expr::Case(open, .., close)
+            | Expr::When(open, .., close)
+            | Expr::Unless(open, .., close)
+            | Expr::And(open, .., close)
+            | Expr::Or(open, .., close)
+            | Expr::Define(open, .., close)
+            | Expr::Set(open, .., close)
+            | Expr::DefineSyntax(open, .., close)
+            | Expr::DefMacro(open, .., close)
+            | Expr::Lambda(open, .., close)
+            | Expr::Let(open, .., close)
+            | Expr::LetStar(open, .., close)
+            | Expr::Letrec(open, .., close)
+            | Expr::NamedLet(open, .., close)
+            | Expr::Module(open, .., close)
+            | Expr::Import(open, .., close)
+            | Expr::Call(open, .., close) => open.span().merge(close.span()),
+            Expr::Expansion(_, _, expr) => expr.span(),
+        }
+    }
+}
+
+/// The `(export <symbol>...)` list of a module
+#[derive(Debug, PartialEq, Clone)]
+pub struct Exports(pub Token, pub Token, pub Vec<Token>, pub Token);
+
+impl Exports {
+    /// Get the tokens of the exported symbols
+    pub fn symbols(&self) -> &[Token] {
+        &self.2
+    }
+}
+
+impl fmt::Display for Exports {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        let Exports(open, export_tok, symbols, close) = self;
+        write!(out, "{}{}", open, export_tok)?;
+        for symbol in symbols.iter() {
+            write!(out, "{}", symbol)?;
+        }
+        write!(out, "{}", close)
+    }
+}
+
+/// A clause of a `cond` form
+#[derive(Debug, PartialEq, Clone)]
+pub enum CondClause {
+    /// `(<test> <expr>...)`, which evaluates the expressions if the
+    /// test is true. With no expressions the value of the test is
+    /// the result.
+    Test(Token, Box<Expr>, Vec<Expr>, Token),
+    /// `(<test> => <expr>)`, which calls the function `<expr>` with
+    /// the value of the test if it is true
+    Arrow(Token, Box<Expr>, Token, Box<Expr>, Token),
+    /// `(else <expr>...)`, which must be the last clause
+    Else(Token, Token, Vec<Expr>, Token),
+}
+
+impl CondClause {
+    /// Get the location of the clause in the source text
+    pub fn span(&self) -> Span {
+        match self {
+            CondClause::Test(open, .., close)
+            | CondClause::Arrow(open, .., close)
+            | CondClause::Else(open, .., close) => open.span().merge(close.span()),
+        }
+    }
+}
+
+impl fmt::Display for CondClause {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CondClause::Test(open, test, body, close) => {
+                write!(out, "{}{}", open, test)?;
+                for expr in body.iter() {
+                    write!(out, "{}", expr)?;
+                }
+                write!(out, "{}", close)
+            }
+            CondClause::Arrow(open, test, arrow, receiver, close) => {
+                write!(out, "{}{}{}{}{}", open, test, arrow, receiver, close)
+            }
+            CondClause::Else(open, else_tok, body, close) => {
+                write!(out, "{}{}", open, else_tok)?;
+                for expr in body.iter() {
+                    write!(out, "{}", expr)?;
+                }
+                write!(out, "{}", close)
+            }
+        }
+    }
+}
+
+/// A clause of a `case` form
+#[derive(Debug, PartialEq, Clone)]
+pub enum CaseClause {
+    /// `((<datum>...) <expr>...)`, which evaluates the expressions if
+    /// the key is one of the data
+    Data(Token, Token, Vec<Datum>, Token, Vec<Expr>, Token),
+    /// `(else <expr>...)`, which must be the last clause
+    Else(Token, Token, Vec<Expr>, Token),
+}
+
+impl CaseClause {
+    /// Get the location of the clause in the source text
+    pub fn span(&self) -> Span {
+        match self {
+            CaseClause::Data(open, .., close) | CaseClause::Else(open, .., close) => {
+                open.span().merge(close.span())
+            }
+        }
+    }
+}
+
+impl fmt::Display for CaseClause {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CaseClause::Data(open, data_open, data, data_close, body, close) => {
+                write!(out, "{}{}", open, data_open)?;
+                for datum in data.iter() {
+                    write!(out, "{}", datum)?;
+                }
+                write!(out, "{}", data_close)?;
+                for expr in body.iter() {
+                    write!(out, "{}", expr)?;
+                }
+                write!(out, "{}", close)
+            }
+            CaseClause::Else(open, else_tok, body, close) => {
+                write!(out, "{}{}", open, else_tok)?;
+                for expr in body.iter() {
+                    write!(out, "{}", expr)?;
+                }
+                write!(out, "{}", close)
+            }
+        }
+    }
+}
+
+/// The `((<symbol> <expr>)...)` list of bindings of a `let` form
+#[derive(Debug, PartialEq, Clone)]
+pub struct LetBindings(pub Token, pub Vec<LetBinding>, pub Token);
+
+impl LetBindings {
+    /// Get each of the bindings, in order
+    pub fn bindings(&self) -> &[LetBinding] {
+        &self.1
+    }
+}
+
+impl fmt::Display for LetBindings {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        let LetBindings(open, bindings, close) = self;
+        write!(out, "{}", open)?;
+        for binding in bindings.iter() {
+            write!(out, "{}", binding)?;
+        }
+        write!(out, "{}", close)
+    }
+}
+
+/// A single `(<symbol> <expr>)` binding of a `let` form
+#[derive(Debug, PartialEq, Clone)]
+pub struct LetBinding(pub Token, pub Token, pub Expr, pub Token);
+
+impl LetBinding {
+    /// Get the token of the bound symbol
+    pub fn name(&self) -> &Token {
+        &self.1
+    }
+
+    /// Get the expression which gives the binding its value
+    pub fn value(&self) -> &Expr {
+        &self.2
+    }
+
+    /// Get the location of the binding in the source text
+    pub fn span(&self) -> Span {
+        self.0.span().merge(self.3.span())
+    }
+}
+
+impl fmt::Display for LetBinding {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        let LetBinding(open, name, value, close) = self;
+        write!(out, "{}{}{}{}", open, name, value, close)
+    }
+}
+
+/// An option to an `import` form
+#[derive(Debug, PartialEq, Clone)]
+pub enum ImportOption {
+    /// `(prefix <symbol>)`, which adds the symbol to the start of
+    /// each imported name
+    Prefix(Token, Token, Token, Token),
+    /// `(rename (<from> <to>)...)`, which binds each `<from>` name
+    /// as `<to>` instead
+    Rename(Token, Token, Vec<(Token, Token, Token, Token)>, Token),
+}
+
+impl ImportOption {
+    /// Get the location of the option in the source text
+    pub fn span(&self) -> Span {
+        match self {
+            ImportOption::Prefix(open, .., close) | ImportOption::Rename(open, .., close) => {
+                open.span().merge(close.span())
+            }
+        }
+    }
+}
+
+impl fmt::Display for ImportOption {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportOption::Prefix(open, prefix_tok, prefix, close) => {
+                write!(out, "{}{}{}{}", open, prefix_tok, prefix, close)
+            }
+            ImportOption::Rename(open, rename_tok, renames, close) => {
+                write!(out, "{}{}", open, rename_tok)?;
+                for (open, from, to, close) in renames.iter() {
+                    write!(out, "{}{}{}{}", open, from, to, close)?;
+                }
+                write!(out, "{}", close)
+            }
+        }
+    }
+}
+
+/// Quoted data
+///
+/// A datum is the literal structure of some source text, without any
+/// meaning as code. Lists hold their brackets and quotes hold the
+/// quote token so that, like `Expr`, a datum can be printed back to
+/// the source it was read from.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Datum {
+    /// A symbol
+    Symbol(Token, String),
+    /// A numeric literal
+    Number(Token, i64),
+    /// A string literal
+    String(Token, String),
+    /// A boolean literal
+    Bool(Token, bool),
+    /// A bracketed list of data
+    List(Token, Vec<Datum>, Token),
+    /// A datum written with the `'` abbreviation, which stands for
+    /// the list `(quote <datum>)`
+    Quote(Token, Box<Datum>),
+}
+
+impl Datum {
+    /// Get the location of the datum in the source text
+    pub fn span(&self) -> Span {
+        match self {
+            Datum::Symbol(token, _) | Datum::Number(token, _) | Datum::String(token, _) => {
+                token.span()
+            }
+            Datum::Bool(token, _) => token.span(),
+            Datum::List(open, _, close) => open.span().merge(close.span()),
+            Datum::Quote(quote, datum) => quote.span().merge(datum.span()),
+        }
+    }
+}
+
+impl fmt::Display for Datum {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Datum::Symbol(token, _) | Datum::String(token, _) => write!(out, "{}", token),
+            Datum::Number(token, _) => write!(out, "{}", token),
+            Datum::Bool(token, _) => write!(out, "{}", token),
+            Datum::List(open, items, close) => {
+                write!(out, "{}", open)?;
+                for item in items.iter() {
+                    write!(out, "{}", item)?;
+                }
+                write!(out, "{}", close)
+            }
+            Datum::Quote(quote, datum) => write!(out, "{}{}", quote, datum),
+        }
+    }
+}
+
+/// A whole source file
+///
+/// Holds each of the top-level forms in the source text in order,
+/// along with the end of file token which carries any trivia after the
+/// final form.
+#[derive(Debug, PartialEq)]
+pub struct Program {
+    forms: Vec<Expr>,
+    end: Token,
+}
+
+impl Program {
+    /// Create a program from the top-level `forms` and `end` token
+    pub fn new(forms: Vec<Expr>, end: Token) -> Self {
+        Program { forms, end }
+    }
+
+    /// Get the top-level forms of the program
+    pub fn forms(&self) -> &[Expr] {
+        &self.forms
+    }
+
+    /// Get the end of file token
+    pub fn end(&self) -> &Token {
+        &self.end
+    }
+
+    /// Take the top-level forms out of the program
+    pub fn into_forms(self) -> Vec<Expr> {
+        self.forms
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        for form in self.forms.iter() {
+            write!(out, "{}", form)?;
+        }
+        write!(out, "{}", self.end)
+    }
+}
+
+/// Print an expression back to source text. As each token keeps its
+/// trivia this reproduces the exact text the expression was parsed
+/// from.
+impl fmt::Display for Expr {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Symbol(token, _) | Expr::String(token, _) => write!(out, "{}", token),
+            Expr::Number(token, _) => write!(out, "{}", token),
+            Expr::Bool(token, _) => write!(out, "{}", token),
+            Expr::If(open, if_tok, cond, if_true, if_false, close) => {
+                write!(out, "{}{}{}{}", open, if_tok, cond, if_true)?;
+                if let Some(if_false) = if_false {
+                    write!(out, "{}", if_false)?;
+                }
+                write!(out, "{}", close)
+            }
+            Expr::Cond(open, cond_tok, clauses, close) => {
+                write!(out, "{}{}", open, cond_tok)?;
+                for clause in clauses.iter() {
+                    write!(out, "{}", clause)?;
+                }
+                write!(out, "{}", close)
+            }
+            Expr::Case(open, case_tok, key, clauses, close) => {
+                write!(out, "{}{}{}", open, case_tok, key)?;
+                for clause in clauses.iter() {
+                    write!(out, "{}", clause)?;
+                }
+                write!(out, "{}", close)
+            }
+            Expr::When(open, kw_tok, cond, body, close)
+            | Expr::Unless(open, kw_tok, cond, body, close) => {
+                write!(out, "{}{}{}", open, kw_tok, cond)?;
+                for expr in body.iter() {
+                    write!(out, "{}", expr)?;
+                }
+                write!(out, "{}", close)
+            }
+            Expr::Define(open, define_tok, sym_tok, value, close)
+            | Expr::Set(open, define_tok, sym_tok, value, close) => {
+                write!(out, "{}{}{}{}{}", open, define_tok, sym_tok, value, close)
+            }
+            Expr::DefineSyntax(open, define_tok, name, rules, close) => {
+                write!(out, "{}{}{}{}{}", open, define_tok, name, rules, close)
+            }
+            Expr::Lambda(open, lambda_tok, params_open, params, params_close, body, close) => {
+                write!(out, "{}{}{}", open, lambda_tok, params_open)?;
+                for param in params.iter() {
+                    write!(out, "{}", param)?;
+                }
+                write!(out, "{}", params_close)?;
+                for expr in body.iter() {
+                    write!(out, "{}", expr)?;
+                }
+                write!(out, "{}", close)
+            }
+            Expr::Let(open, let_tok, bindings, body, close)
+            | Expr::LetStar(open, let_tok, bindings, body, close)
+            | Expr::Letrec(open, let_tok, bindings, body, close) => {
+                write!(out, "{}{}{}", open, let_tok, bindings)?;
+                for expr in body.iter() {
+                    write!(out, "{}", expr)?;
+                }
+                write!(out, "{}", close)
+            }
+            Expr::NamedLet(open, let_tok, name, bindings, body, close) => {
+                write!(out, "{}{}{}{}", open, let_tok, name, bindings)?;
+                for expr in body.iter() {
+                    write!(out, "{}", expr)?;
+                }
+                write!(out, "{}", close)
+            }
+            Expr::DefMacro(
+                open,
+                defmacro_tok,
+                name,
+                params_open,
+                params,
+                params_close,
+                body,
+                close,
+            ) => {
+                write!(out, "{}{}{}{}", open, defmacro_tok, name, params_open)?;
+                for param in params.iter() {
+                    write!(out, "{}", param)?;
+                }
+                write!(out, "{}", params_close)?;
+                for expr in body.iter() {
+                    write!(out, "{}", expr)?;
+                }
+                write!(out, "{}", close)
+            }
+            Expr::Module(open, module_tok, name, exports, body, close) => {
+                write!(out, "{}{}{}{}", open, module_tok, name, exports)?;
+                for expr in body.iter() {
+                    write!(out, "{}", expr)?;
+                }
+                write!(out, "{}", close)
+            }
+            Expr::Import(open, import_tok, name, options, close) => {
+                write!(out, "{}{}{}", open, import_tok, name)?;
+                for option in options.iter() {
+                    write!(out, "{}", option)?;
+                }
+                write!(out, "{}", close)
+            }
+            Expr::Quote(open, quote_tok, datum, close) => {
+                write!(out, "{}{}{}{}", open, quote_tok, datum, close)
+            }
+            Expr::QuoteAbbrev(quote, datum) => write!(out, "{}{}", quote, datum),
+            Expr::And(open, kw_tok, args, close) | Expr::Or(open, kw_tok, args, close) => {
+                write!(out, "{}{}", open, kw_tok)?;
+                for arg in args.iter() {
+                    write!(out, "{}", arg)?;
+                }
+                write!(out, "{}", close)
+            }
+            Expr::Call(open, callee, args, close) => {
+                write!(out, "{}{}", open, callee)?;
+                for arg in args.iter() {
+                    write!(out, "{}", arg)?;
+                }
+                write!(out, "{}", close)
+            }
+            Expr::Expansion(_, _, expr) => write!(out, "{}", expr),
+        }
+    }
 }
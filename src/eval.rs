@@ -2,41 +2,152 @@
 //!
 //! This module is responsible for walking expression trees and
 //! evaluating the programs that they represent. It revolves around
-//! the `eval` method.
+//! the `eval_with_env` method.
 
 use super::ast;
+use super::expand;
+use super::module::{self, Module, Modules};
+use super::native::{Arity, NativeFunction};
+use codespan::Span;
 
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::fmt;
+use std::rc::Rc;
 
 /// Stores one of the varying value kinds that are used in
 /// evaluation. This can be the result of evaluating an expression or
 /// stored in an environment.
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub enum Value {
     /// A numeric value
     Number(i64),
-    /// A callable value
-    Callable(Callable),
+    /// A boolean value
+    Bool(bool),
+    /// An immutable string value
+    String(Rc<str>),
+    /// A symbol, as produced by quoting
+    Symbol(Rc<str>),
+    /// A pair of values. Lists are chains of pairs ending in `Nil`.
+    Pair(Rc<Pair>),
+    /// A function implemented in Rust
+    Native(Rc<NativeFunction>),
+    /// A user-defined function
+    Lambda(Rc<Closure>),
     /// The empty list and an invalid or placeholder value
     Nil,
 }
 
 impl Value {
     /// Check the trunthyness of a given value
-    fn is_truthy(&self) -> bool {
-        use Value::*;
-        match *self {
-            Number(n) => n != 0,
-            _ => true,
+    ///
+    /// Only `#f` and `nil` are false. Every other value, including
+    /// `0` and the empty string, is true.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(*self, Value::Bool(false) | Value::Nil)
+    }
+
+    /// Create a list from the given values
+    pub fn list<I>(values: I) -> Value
+    where
+        I: IntoIterator<Item = Value>,
+        I::IntoIter: DoubleEndedIterator,
+    {
+        values
+            .into_iter()
+            .rev()
+            .fold(Value::Nil, |tail, head| Value::cons(head, tail))
+    }
+
+    /// Create a pair from the two given values
+    pub fn cons(car: Value, cdr: Value) -> Value {
+        Value::Pair(Rc::new(Pair(car, cdr)))
+    }
+
+    /// Collect the elements of a proper list
+    fn to_vec(&self) -> Result<Vec<Value>, EvalError> {
+        let mut values = Vec::new();
+        let mut current = self;
+        loop {
+            match current {
+                Value::Nil => return Ok(values),
+                Value::Pair(pair) => {
+                    values.push(pair.0.clone());
+                    current = &pair.1;
+                }
+                _ => return Err(EvalError::type_mismatch("list", self)),
+            }
+        }
+    }
+
+    /// Check if two values are the same object, as `eq?`
+    ///
+    /// Numbers, booleans, symbols and `nil` are compared by
+    /// value. Strings, pairs and functions are only the same if they
+    /// share the same allocation.
+    pub fn is_eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::String(a), Value::String(b)) => Rc::ptr_eq(a, b),
+            (Value::Symbol(a), Value::Symbol(b)) => a == b,
+            (Value::Pair(a), Value::Pair(b)) => Rc::ptr_eq(a, b),
+            (Value::Native(a), Value::Native(b)) => Rc::ptr_eq(a, b),
+            (Value::Lambda(a), Value::Lambda(b)) => Rc::ptr_eq(a, b),
+            (Value::Nil, Value::Nil) => true,
+            _ => false,
+        }
+    }
+
+    /// Check if two values are structurally equal, as `equal?`
+    ///
+    /// This is the same as `is_eq` except that strings with the same
+    /// contents are equal, and pairs are equal if their elements are.
+    pub fn is_equal(&self, other: &Value) -> bool {
+        let (mut a, mut b) = (self, other);
+        // Walk along the tails of lists rather than recursing into them,
+        // so long lists can be compared.
+        loop {
+            match (a, b) {
+                (Value::String(x), Value::String(y)) => return x == y,
+                (Value::Pair(x), Value::Pair(y)) => {
+                    if !x.0.is_equal(&y.0) {
+                        return false;
+                    }
+                    a = &x.1;
+                    b = &y.1;
+                }
+                _ => return a.is_eq(b),
+            }
         }
     }
 
     /// Convert a value to a number
-    fn into_num(self) -> i64 {
+    fn as_num(&self) -> Result<i64, EvalError> {
+        match *self {
+            Value::Number(n) => Ok(n),
+            ref other => Err(EvalError::type_mismatch("number", other)),
+        }
+    }
+
+    /// Borrow the text of a string value
+    fn as_str(&self) -> Result<&str, EvalError> {
         match self {
-            Value::Number(n) => n,
-            other => panic!("can't use {:?}, it isn't a number", other),
+            Value::String(s) => Ok(s),
+            other => Err(EvalError::type_mismatch("string", other)),
+        }
+    }
+
+    /// Get the name of the type of this value
+    pub fn type_name(&self) -> &'static str {
+        match *self {
+            Value::Number(_) => "number",
+            Value::Bool(_) => "boolean",
+            Value::String(_) => "string",
+            Value::Symbol(_) => "symbol",
+            Value::Pair(_) => "pair",
+            Value::Native(_) | Value::Lambda(_) => "function",
+            Value::Nil => "nil",
         }
     }
 }
@@ -45,22 +156,274 @@ impl fmt::Display for Value {
     fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Value::Number(n) => write!(out, "{}", n),
-            Value::Callable(c) => write!(out, "<callable {:x?}>", c),
+            Value::Bool(b) => write!(out, "{}", if b { "#t" } else { "#f" }),
+            Value::String(ref s) => write!(out, "{}", s),
+            Value::Symbol(ref s) => write!(out, "{}", s),
+            Value::Pair(ref pair) => {
+                // Strings inside a list are written as literals so that
+                // the list reads back as the same data.
+                let write_item = |out: &mut fmt::Formatter, item: &Value| match item {
+                    Value::String(s) => write!(out, "{}", ast::escape(s)),
+                    other => write!(out, "{}", other),
+                };
+                write!(out, "(")?;
+                write_item(out, &pair.0)?;
+                let mut tail = &pair.1;
+                while let Value::Pair(pair) = tail {
+                    write!(out, " ")?;
+                    write_item(out, &pair.0)?;
+                    tail = &pair.1;
+                }
+                if !matches!(tail, Value::Nil) {
+                    write!(out, " . ")?;
+                    write_item(out, tail)?;
+                }
+                write!(out, ")")
+            }
+            Value::Native(ref native) => write!(out, "<native {}>", native.name()),
+            Value::Lambda(ref closure) => write!(out, "<lambda ({})>", closure.params.join(" ")),
             Value::Nil => write!(out, "nil"),
         }
     }
 }
 
+/// The two halves of a pair, its `car` and `cdr`
+#[derive(Debug)]
+pub struct Pair(pub Value, pub Value);
+
+impl Drop for Pair {
+    /// Dropping a list would otherwise drop each pair from within the
+    /// drop of the one before it, which runs out of stack for long
+    /// lists. Instead the tail of each pair which isn't shared is
+    /// detached and dropped in a loop.
+    fn drop(&mut self) {
+        let mut tail = std::mem::replace(&mut self.1, Value::Nil);
+        while let Value::Pair(pair) = &mut tail {
+            match Rc::get_mut(pair) {
+                Some(pair) => {
+                    let next = std::mem::replace(&mut pair.1, Value::Nil);
+                    tail = next;
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+/// The kinds of evaluation error
+#[derive(Debug, PartialEq, Clone)]
+pub enum EvalErrorKind {
+    /// A symbol was referenced which isn't bound in the environment
+    UndefinedSymbol(String),
+    /// A value of the wrong type was used
+    TypeMismatch {
+        /// The name of the type which was expected
+        expected: &'static str,
+        /// A description of the value which was found
+        found: String,
+    },
+    /// A function was called with the wrong number of arguments
+    ArityMismatch {
+        /// The name of the function being called
+        name: String,
+        /// The number of arguments the function accepts
+        expected: Arity,
+        /// The number of arguments it was given
+        found: usize,
+    },
+    /// A number was divided by zero
+    DivisionByZero,
+    /// The result of an arithmetic operation doesn't fit in a number
+    Overflow,
+    /// A value which isn't a function was called
+    NotCallable(String),
+    /// A symbol was defined in a scope which already binds it
+    AlreadyDefined(String),
+    /// Expressions were nested too deeply to evaluate, usually because
+    /// of a recursive call which isn't in tail position
+    RecursionTooDeep,
+    /// Any other error, described by a message
+    Custom(String),
+}
+
+impl EvalErrorKind {
+    /// Get the error code for this kind of error
+    pub fn code(&self) -> &'static str {
+        match self {
+            EvalErrorKind::UndefinedSymbol(_) => "E0001",
+            EvalErrorKind::TypeMismatch { .. } => "E0002",
+            EvalErrorKind::ArityMismatch { .. } => "E0003",
+            EvalErrorKind::DivisionByZero => "E0004",
+            EvalErrorKind::NotCallable(_) => "E0005",
+            EvalErrorKind::Custom(_) => "E0006",
+            EvalErrorKind::Overflow => "E0007",
+            EvalErrorKind::AlreadyDefined(_) => "E0008",
+            EvalErrorKind::RecursionTooDeep => "E0009",
+        }
+    }
+}
+
+impl fmt::Display for EvalErrorKind {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalErrorKind::UndefinedSymbol(sym) => write!(out, "undefined symbol `{}`", sym),
+            EvalErrorKind::TypeMismatch { expected, found } => {
+                write!(out, "expected {}, found {}", expected, found)
+            }
+            EvalErrorKind::ArityMismatch {
+                name,
+                expected,
+                found,
+            } => write!(
+                out,
+                "wrong number of arguments to `{}`: expected {}, found {}",
+                name, expected, found
+            ),
+            EvalErrorKind::DivisionByZero => write!(out, "division by zero"),
+            EvalErrorKind::Overflow => write!(out, "arithmetic overflow"),
+            EvalErrorKind::NotCallable(value) => write!(out, "{} is not callable", value),
+            EvalErrorKind::AlreadyDefined(sym) => write!(
+                out,
+                "`{}` is already defined, use `set!` to change its value",
+                sym
+            ),
+            EvalErrorKind::RecursionTooDeep => write!(out, "recursion too deep"),
+            EvalErrorKind::Custom(message) => write!(out, "{}", message),
+        }
+    }
+}
+
+/// A single call or macro expansion in an evaluation error's trace
+#[derive(Debug, PartialEq, Clone)]
+pub struct Frame {
+    /// Whether this is a call or an expansion
+    pub kind: FrameKind,
+    /// The name of the function which was called, or of the macro
+    pub name: String,
+    /// The location of the call expression, or of the macro use
+    pub span: Span,
+}
+
+/// The kinds of frame in an evaluation error's trace
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FrameKind {
+    /// A call to a function
+    Call,
+    /// The code a macro use expanded into
+    Expansion,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            FrameKind::Call => write!(out, "in call to `{}`", self.name),
+            FrameKind::Expansion => write!(out, "in expansion of `{}`", self.name),
+        }
+    }
+}
+
 /// Evaluation error values
 ///
-/// This contains the different kinds of errors that can occur when
-/// evaluating a value.
+/// This contains the kind of error that occured when evaluating a
+/// value, along with the location of the expression which raised it
+/// and the trace of calls which were being evaluated at the time.
 #[derive(Debug, PartialEq)]
-pub struct EvalError(String);
+pub struct EvalError {
+    kind: EvalErrorKind,
+    span: Option<Span>,
+    trace: Vec<Frame>,
+}
+
+impl EvalError {
+    /// Create an evaluation error with the given `message`
+    pub fn new(message: impl Into<String>) -> Self {
+        EvalErrorKind::Custom(message.into()).into()
+    }
+
+    /// Create an error for a `value` which isn't of the `expected`
+    /// type
+    pub fn type_mismatch(expected: &'static str, value: &Value) -> Self {
+        EvalErrorKind::TypeMismatch {
+            expected,
+            found: format!("{} `{}`", value.type_name(), value),
+        }
+        .into()
+    }
+
+    /// Get the kind of error
+    pub fn kind(&self) -> &EvalErrorKind {
+        &self.kind
+    }
+
+    /// Get the location of the expression which raised the error, if
+    /// known
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+
+    /// Get the calls which were being evaluated when the error was
+    /// raised, innermost first
+    pub fn trace(&self) -> &[Frame] {
+        &self.trace
+    }
+
+    /// Set the location of the error, unless it is already known
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span.get_or_insert(span);
+        self
+    }
+
+    /// Record that the error passed out of a call to the function
+    /// `name` at `span`. Errors without a location were raised by the
+    /// call itself so take its location rather than adding a frame.
+    fn in_call(self, name: &str, span: Span) -> Self {
+        self.in_frame(FrameKind::Call, name, span)
+    }
+
+    /// Record that the error passed out of the expansion of a use of
+    /// the macro `name` at `span`
+    fn in_expansion(self, name: &str, span: Span) -> Self {
+        self.in_frame(FrameKind::Expansion, name, span)
+    }
+
+    /// Add a frame to the trace, or use its location as the error's
+    /// location if it doesn't have one
+    fn in_frame(self, kind: FrameKind, name: &str, span: Span) -> Self {
+        match self.span {
+            None => self.with_span(span),
+            Some(_) => {
+                let mut error = self;
+                error.trace.push(Frame {
+                    kind,
+                    name: name.into(),
+                    span,
+                });
+                error
+            }
+        }
+    }
+}
+
+impl From<EvalErrorKind> for EvalError {
+    fn from(kind: EvalErrorKind) -> Self {
+        EvalError {
+            kind,
+            span: None,
+            trace: Vec::new(),
+        }
+    }
+}
 
 impl fmt::Display for EvalError {
     fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
-        write!(out, "error: {}", self.0)
+        write!(out, "error[{}]: {}", self.kind.code(), self.kind)?;
+        if let Some(span) = self.span {
+            write!(out, " at {}", span)?;
+        }
+        for frame in self.trace.iter() {
+            write!(out, "\n  {} at {}", frame, frame.span)?;
+        }
+        Ok(())
     }
 }
 
@@ -70,58 +433,816 @@ impl fmt::Display for EvalError {
 /// success or an `EvalError` on failure.
 pub type EvalResult = Result<Value, EvalError>;
 
-/// The type of a funtion call in our LISP
-type Callable = fn(Vec<Value>) -> EvalResult;
+/// Evaluation environment
+///
+/// Environments form a chain of scopes. Each scope holds its own
+/// bindings and refers to the scope it was created in. Lookups walk
+/// out along the chain, so inner bindings shadow outer ones.
+///
+/// Every scope in a chain shares the same table of modules.
+///
+/// The root of the chain made by `make_global_env` is a prelude holding
+/// the builtins. Programs are evaluated in the global scope nested
+/// within it, so their definitions shadow the builtins rather than
+/// replacing them.
+pub struct Environment {
+    bindings: RefCell<HashMap<String, Value>>,
+    parent: Option<Rc<Environment>>,
+    modules: Rc<Modules>,
+    redefinable: Cell<bool>,
+    module: bool,
+}
+
+impl Environment {
+    /// Create a new, empty, root environment with no modules
+    pub fn new() -> Rc<Self> {
+        Rc::new(Environment {
+            bindings: RefCell::new(HashMap::new()),
+            parent: None,
+            modules: Rc::default(),
+            redefinable: Cell::new(false),
+            module: false,
+        })
+    }
+
+    /// Create a new, empty, scope nested within `parent`
+    pub fn with_parent(parent: &Rc<Environment>) -> Rc<Self> {
+        Rc::new(Environment {
+            bindings: RefCell::new(HashMap::new()),
+            parent: Some(Rc::clone(parent)),
+            modules: Rc::clone(&parent.modules),
+            redefinable: Cell::new(false),
+            module: false,
+        })
+    }
+
+    /// Create a new, empty, scope for the body of a module nested
+    /// within `parent`
+    pub fn for_module(parent: &Rc<Environment>) -> Rc<Self> {
+        Rc::new(Environment {
+            bindings: RefCell::new(HashMap::new()),
+            parent: Some(Rc::clone(parent)),
+            modules: Rc::clone(&parent.modules),
+            redefinable: Cell::new(false),
+            module: true,
+        })
+    }
+
+    /// Get the top-level scope this scope is nested within. This is
+    /// the scope of the module it is in, or else the global scope.
+    pub fn top_level(self: &Rc<Self>) -> Rc<Self> {
+        let mut scope = self;
+        while let Some(parent) = scope.parent.as_ref().filter(|p| p.parent.is_some()) {
+            if scope.module {
+                break;
+            }
+            scope = parent;
+        }
+        Rc::clone(scope)
+    }
+
+    /// Get the global scope this scope is nested within. This is the
+    /// outermost scope below the root, or the root itself if this is
+    /// the root.
+    pub fn global(self: &Rc<Self>) -> Rc<Self> {
+        let mut scope = self;
+        while let Some(parent) = scope.parent.as_ref().filter(|p| p.parent.is_some()) {
+            scope = parent;
+        }
+        Rc::clone(scope)
+    }
+
+    /// Check if `define` can replace the bindings already in this
+    /// scope
+    pub fn is_redefinable(&self) -> bool {
+        self.redefinable.get()
+    }
+
+    /// Set whether `define` can replace the bindings already in this
+    /// scope, rather than it being an error. Scopes start out without
+    /// allowing this.
+    pub fn set_redefinable(&self, redefinable: bool) {
+        self.redefinable.set(redefinable);
+    }
+
+    /// Get the scope this one is nested within, if it isn't the root
+    pub fn parent(&self) -> Option<&Rc<Environment>> {
+        self.parent.as_ref()
+    }
+
+    /// Get the modules known to this chain of scopes
+    pub fn modules(&self) -> &Modules {
+        &self.modules
+    }
+
+    /// Look up the value bound to `sym` in the nearest enclosing
+    /// scope which defines it
+    pub fn get(&self, sym: &str) -> Option<Value> {
+        let mut scope = self;
+        loop {
+            if let Some(value) = scope.bindings.borrow().get(sym) {
+                return Some(value.clone());
+            }
+            scope = scope.parent.as_ref()?;
+        }
+    }
+
+    /// Get each of the bindings in this scope, sorted by name. Scopes
+    /// this one is nested within are not included.
+    pub fn bindings(&self) -> Vec<(String, Value)> {
+        let mut bindings = self
+            .bindings
+            .borrow()
+            .iter()
+            .map(|(sym, value)| (sym.clone(), value.clone()))
+            .collect::<Vec<_>>();
+        bindings.sort_by(|a, b| a.0.cmp(&b.0));
+        bindings
+    }
+
+    /// Bind `sym` to `value` in this scope, replacing any existing
+    /// binding
+    pub fn define(&self, sym: String, value: Value) {
+        self.bindings.borrow_mut().insert(sym, value);
+    }
+
+    /// Check if `sym` is bound in this scope. Scopes this one is
+    /// nested within are not checked.
+    pub fn defines(&self, sym: &str) -> bool {
+        self.bindings.borrow().contains_key(sym)
+    }
+
+    /// Change the value bound to `sym` in the nearest enclosing scope
+    /// which defines it. Returns `false` if no scope does.
+    pub fn set(&self, sym: &str, value: Value) -> bool {
+        let mut scope = self;
+        loop {
+            if let Some(binding) = scope.bindings.borrow_mut().get_mut(sym) {
+                *binding = value;
+                return true;
+            }
+            match &scope.parent {
+                Some(parent) => scope = parent,
+                None => return false,
+            }
+        }
+    }
+
+    /// Bind a native function in this scope under its own name
+    pub fn define_native(&self, function: NativeFunction) {
+        self.define(function.name().into(), Value::Native(Rc::new(function)));
+    }
+}
 
-/// Simple Evaluation
+/// A user-defined function
 ///
-/// Convenience function to evaluate a given expression in a new
-/// environment. This is used by the main driver when evaluating
-/// expressions from a function.
-pub fn eval(expr: ast::Expr) -> EvalResult {
-    eval_with_env(expr, &mut make_global_env())
+/// Closures are created by evaluating a `lambda` expression. They
+/// capture the environment the `lambda` was evaluated in, and each
+/// call evaluates the body in a new scope nested within it.
+pub struct Closure {
+    params: Vec<String>,
+    body: Rc<[ast::Expr]>,
+    env: Rc<Environment>,
+}
+
+impl Closure {
+    /// Create a closure which evaluates `body` with `params` bound in
+    /// a scope nested within `env`
+    pub(crate) fn new(params: Vec<String>, body: Rc<[ast::Expr]>, env: Rc<Environment>) -> Self {
+        Closure { params, body, env }
+    }
+
+    /// Create the scope for a call to the closure, with each
+    /// parameter bound to its argument
+    fn bind(&self, args: Vec<Value>) -> Result<Rc<Environment>, EvalError> {
+        if args.len() != self.params.len() {
+            return Err(EvalErrorKind::ArityMismatch {
+                name: "<lambda>".into(),
+                expected: Arity::Exact(self.params.len()),
+                found: args.len(),
+            }
+            .into());
+        }
+        let env = Environment::with_parent(&self.env);
+        for (param, arg) in self.params.iter().zip(args) {
+            env.define(param.clone(), arg);
+        }
+        Ok(env)
+    }
+
+    /// Evaluate the body of the closure in the scope for a call
+    fn eval_body(&self, env: &Rc<Environment>) -> Result<Step, EvalError> {
+        eval_tail_body(&self.body, env)
+    }
+}
+
+// Closures can refer to themselves through their environment, so
+// only the parameters are shown.
+impl fmt::Debug for Closure {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        out.debug_struct("Closure")
+            .field("params", &self.params)
+            .finish()
+    }
+}
+
+/// Program evaluation function. Evaluates each top-level form of the
+/// program in order in the given environment. Returns the value of
+/// the last form, or `Nil` if the program is empty.
+///
+/// The program is only borrowed, so a parsed program can be evaluated
+/// as many times as needed.
+pub fn eval_program(program: &ast::Program, env: &Rc<Environment>) -> EvalResult {
+    eval_body(program.forms(), env)
+}
+
+/// Evaluate a program read from a source file
+///
+/// This is the same as `eval_program`, except that a file which starts
+/// with a `module` form with no body is a module file, and the rest of
+/// the file is the module's body.
+pub(crate) fn eval_file_program(program: &ast::Program, env: &Rc<Environment>) -> EvalResult {
+    match program.forms() {
+        [header, rest @ ..] if is_module_header(header) => eval_module(header, rest, env),
+        forms => eval_body(forms, env),
+    }
+}
+
+/// Check if `form` is a `module` form with no body, which makes the
+/// rest of the file it starts the module's body
+pub(crate) fn is_module_header(form: &ast::Expr) -> bool {
+    matches!(form, ast::Expr::Module(.., body, _) if body.is_empty())
+}
+
+/// Evaluate each of the `forms` in turn, returning the value of the
+/// last, or `Nil` if there are none
+fn eval_body(forms: &[ast::Expr], env: &Rc<Environment>) -> EvalResult {
+    let mut result = Value::Nil;
+    for form in forms {
+        result = eval_with_env(form, env)?;
+    }
+    Ok(result)
+}
+
+/// Evaluate each of the `forms` in turn. The last is in tail position,
+/// so is only evaluated until it reaches a call to a closure.
+fn eval_tail_body(forms: &[ast::Expr], env: &Rc<Environment>) -> Result<Step, EvalError> {
+    match forms.split_last() {
+        Some((last, init)) => {
+            for expr in init.iter() {
+                eval_with_env(expr, env)?;
+            }
+            eval_step(last, env)
+        }
+        None => Ok(Step::Done(Value::Nil)),
+    }
+}
+
+/// Define the module declared by the `module` form `header`, with the
+/// given `body`
+///
+/// The body is evaluated in a new scope nested within `env`, and the
+/// module is added to the module table once every binding it exports
+/// has been defined.
+fn eval_module(header: &ast::Expr, body: &[ast::Expr], env: &Rc<Environment>) -> EvalResult {
+    let module_env = Environment::for_module(env);
+    eval_body(body, &module_env)?;
+    register_module(header, module_env)
+}
+
+/// Add the module declared by the `module` form `header` to the module
+/// table, once its body has been evaluated in `module_env`
+pub(crate) fn register_module(header: &ast::Expr, module_env: Rc<Environment>) -> EvalResult {
+    let (name, export_toks) = match header {
+        ast::Expr::Module(_, _, name, exports, ..) => (name, exports.symbols()),
+        _ => unreachable!("module header should be a module form"),
+    };
+    let name = to_sym(name)?;
+    let mut exports = Vec::with_capacity(export_toks.len());
+    for token in export_toks.iter() {
+        let export = to_sym(token)?;
+        if !module_env.defines(&export) {
+            return Err(EvalError::new(format!(
+                "module `{}` exports `{}` but doesn't define it",
+                name, export
+            ))
+            .with_span(token.span()));
+        }
+        exports.push(export);
+    }
+    module_env
+        .modules()
+        .register(Module::new(name, Rc::clone(&module_env), exports));
+    Ok(Value::Nil)
+}
+
+/// Bind the exports of the module `name` in `env`, with their names
+/// changed by the import `options`
+fn eval_import(
+    name: &ast::Token,
+    options: &[ast::ImportOption],
+    env: &Rc<Environment>,
+) -> EvalResult {
+    let module = module::import(env, &to_sym(name)?).map_err(|e| e.with_span(name.span()))?;
+    let mut names = module
+        .exports()
+        .iter()
+        .map(|export| (export.clone(), export.clone()))
+        .collect::<Vec<_>>();
+    for option in options.iter() {
+        match option {
+            ast::ImportOption::Prefix(_, _, prefix, _) => {
+                let prefix = to_sym(prefix)?;
+                for (local, _) in names.iter_mut() {
+                    local.insert_str(0, &prefix);
+                }
+            }
+            ast::ImportOption::Rename(_, _, renames, _) => {
+                for (_, from, to, _) in renames.iter() {
+                    let from_sym = to_sym(from)?;
+                    let local = names
+                        .iter_mut()
+                        .find(|(local, _)| *local == from_sym)
+                        .map(|(local, _)| local)
+                        .ok_or_else(|| {
+                            EvalError::new(format!(
+                                "module `{}` doesn't export `{}`",
+                                module.name(),
+                                from_sym
+                            ))
+                            .with_span(from.span())
+                        })?;
+                    *local = to_sym(to)?;
+                }
+            }
+        }
+    }
+    for (local, export) in names {
+        // Exports are checked when the module is defined
+        let value = module.get(&export).unwrap_or(Value::Nil);
+        env.define(local, value);
+    }
+    Ok(Value::Nil)
+}
+
+/// Look up a symbol of the form `module/name` in the module table
+fn lookup_qualified(sym: &str, env: &Environment) -> Option<Value> {
+    let (module, name) = sym.split_once('/')?;
+    env.modules().get(module)?.get(name)
+}
+
+/// The result of evaluating an expression up to its tail position
+enum Step {
+    /// Evaluation finished with the given value
+    Done(Value),
+    /// Evaluation ends with a call to a closure. This holds the
+    /// closure, its arguments, and the name and location of the call.
+    Call(Rc<Closure>, Vec<Value>, String, Span),
 }
 
 /// Main evaluation function. This function accepts a parsed syntax
 /// tree and evaluates it into a single Value using the given
-/// environment..
-pub fn eval_with_env(expr: ast::Expr, env: &mut HashMap<String, Value>) -> EvalResult {
+/// environment.
+///
+/// Expressions in tail position, such as the branches of an `if` or
+/// the last expression in the body of a `lambda`, are evaluated in a
+/// loop rather than by recursion. This means tail calls run in
+/// constant Rust stack space, so recursive loops can run for as many
+/// iterations as they like.
+///
+/// Other expressions are evaluated recursively. Their nesting is
+/// limited to the recursion limit of the program, so runaway recursion
+/// is reported as an error rather than overflowing the Rust stack.
+pub fn eval_with_env(expr: &ast::Expr, env: &Rc<Environment>) -> EvalResult {
+    let limit = env.modules().recursion_limit();
+    let _depth = DepthGuard::enter(limit).map_err(|e| e.with_span(expr.span()))?;
+    run(eval_step(expr, env))
+}
+
+/// The deepest that calls to `eval_with_env` can nest by default
+///
+/// Each level takes up to around 3KB of stack in a release build, and
+/// 20KB in a debug build. This leaves room for the host on a 2MB thread
+/// in a release build, the size Rust gives threads by default, and on
+/// the 8MB main thread in a debug build.
+pub const DEFAULT_RECURSION_LIMIT: usize = 300;
+
+thread_local! {
+    /// The number of calls to `eval_with_env` in progress
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Counts an evaluation in progress for as long as it is alive
+struct DepthGuard;
+
+impl DepthGuard {
+    /// Count a new evaluation, or fail if `limit` are in progress
+    /// already
+    fn enter(limit: usize) -> Result<Self, EvalError> {
+        DEPTH.with(|depth| {
+            if depth.get() >= limit {
+                return Err(EvalError::from(EvalErrorKind::RecursionTooDeep));
+            }
+            depth.set(depth.get() + 1);
+            Ok(DepthGuard)
+        })
+    }
+}
+
+impl Drop for DepthGuard {
+    fn drop(&mut self) {
+        DEPTH.with(|depth| depth.set(depth.get() - 1));
+    }
+}
+
+/// Call `function` with `args`. The `name` and `span` of the call are
+/// used to report errors.
+pub(crate) fn apply(function: &Value, args: Vec<Value>, name: &str, span: Span) -> EvalResult {
+    match function {
+        Value::Native(native) => native.call(args).map_err(|e| e.in_call(name, span)),
+        Value::Lambda(closure) => run(Ok(Step::Call(Rc::clone(closure), args, name.into(), span))),
+        other => {
+            let kind = EvalErrorKind::NotCallable(format!("{} `{}`", other.type_name(), other));
+            Err(EvalError::from(kind).with_span(span))
+        }
+    }
+}
+
+/// Run the calls in tail position which follow from `step` until one
+/// produces a value
+fn run(mut step: Result<Step, EvalError>) -> EvalResult {
+    // The call which the loop most recently jumped into. Tail calls
+    // replace this rather than adding to it, so the trace of an error
+    // only includes the calls which are still waiting for a result.
+    let mut frame: Option<(String, Span)> = None;
+    loop {
+        let (closure, args, name, span) = match step {
+            Ok(Step::Done(value)) => return Ok(value),
+            Ok(Step::Call(closure, args, name, span)) => (closure, args, name, span),
+            Err(error) => {
+                return Err(match frame {
+                    Some((name, span)) => error.in_call(&name, span),
+                    None => error,
+                })
+            }
+        };
+        step = match closure.bind(args) {
+            Ok(env) => {
+                frame = Some((name, span));
+                closure.eval_body(&env)
+            }
+            Err(error) => Err(error.in_call(&name, span)),
+        };
+    }
+}
+
+/// Evaluate an expression until it either produces a value or reaches
+/// a call to a closure in tail position.
+fn eval_step(mut expr: &ast::Expr, env: &Rc<Environment>) -> Result<Step, EvalError> {
     use ast::Expr::*;
-    match expr {
-        Symbol(_, s) => env
-            .get(&s)
-            .cloned()
-            .ok_or_else(|| EvalError(format!("eval: Undefined symbol {}", s))),
-        Number(_, n) => Ok(Value::Number(n)),
-        If(_, _, cond, then, elz, _) => Ok(if eval_with_env(*cond, env)?.is_truthy() {
-            eval_with_env(*then, env)?
-        } else {
-            eval_with_env(*elz, env)?
-        }),
-        Define(_, _, sym, value, _) => {
-            let value = eval_with_env(*value, env)?;
-            let sym = to_sym(sym)?;
-            env.insert(sym, value.clone());
-            Ok(value)
-        }
-        Call(_, sym, args, _) => {
-            let sym = to_sym(sym)?;
-            match env.get(&sym) {
-                Some(Value::Callable(c)) => c(args
-                    .into_iter()
-                    .map(|a| eval_with_env(a, env))
-                    .collect::<Result<Vec<_>, _>>()?),
-                _ => Err(EvalError(format!("eval: Invalid function {}", sym))),
+    loop {
+        let span = expr.span();
+        expr = match expr {
+            Symbol(_, s) => return eval_symbol(s, span, env).map(Step::Done),
+            Number(_, n) => return Ok(Step::Done(Value::Number(*n))),
+            String(_, s) => return Ok(Step::Done(Value::String(s.as_str().into()))),
+            Bool(_, b) => return Ok(Step::Done(Value::Bool(*b))),
+            Quote(_, _, datum, _) | QuoteAbbrev(_, datum) => {
+                return Ok(Step::Done(datum_to_value(datum)))
+            }
+            If(_, _, cond, then, elz, _) => {
+                if eval_with_env(cond, env)?.is_truthy() {
+                    then
+                } else {
+                    match elz {
+                        Some(elz) => elz,
+                        None => return Ok(Step::Done(Value::Nil)),
+                    }
+                }
+            }
+            Cond(_, _, clauses, _) => return eval_cond(clauses, env),
+            Case(_, _, key, clauses, _) => return eval_case(key, clauses, env),
+            When(_, _, cond, body, _) | Unless(_, _, cond, body, _) => {
+                let truthy = eval_with_env(cond, env)?.is_truthy();
+                return if truthy == matches!(expr, When(..)) {
+                    eval_tail_body(body, env)
+                } else {
+                    Ok(Step::Done(Value::Nil))
+                };
+            }
+            Define(_, _, sym_tok, value, _) => return eval_define(sym_tok, value, env),
+            Set(_, _, sym_tok, value, _) => return eval_set(sym_tok, value, env),
+            And(_, _, args, _) => match args.split_last() {
+                Some((last, init)) => {
+                    for arg in init.iter() {
+                        let result = eval_with_env(arg, env)?;
+                        if !result.is_truthy() {
+                            return Ok(Step::Done(result));
+                        }
+                    }
+                    last
+                }
+                None => return Ok(Step::Done(Value::Bool(true))),
+            },
+            Or(_, _, args, _) => match args.split_last() {
+                Some((last, init)) => {
+                    for arg in init.iter() {
+                        let result = eval_with_env(arg, env)?;
+                        if result.is_truthy() {
+                            return Ok(Step::Done(result));
+                        }
+                    }
+                    last
+                }
+                None => return Ok(Step::Done(Value::Bool(false))),
+            },
+            // Macros are defined when they are parsed
+            DefineSyntax(..) | DefMacro(..) => return Ok(Step::Done(Value::Nil)),
+            Module(.., body, _) => return eval_module(expr, body, env).map(Step::Done),
+            Import(_, _, name, options, _) => {
+                return eval_import(name, options, env).map(Step::Done)
+            }
+            Lambda(_, _, _, params, _, body, _) => {
+                return eval_lambda(params, body, env).map(Step::Done)
+            }
+            Let(_, _, bindings, body, _) => return eval_let(bindings, body, env),
+            LetStar(_, _, bindings, body, _) => return eval_let_star(bindings, body, env),
+            Letrec(_, _, bindings, body, _) => return eval_letrec(bindings, body, env),
+            NamedLet(_, _, name, bindings, body, _) => {
+                return eval_named_let(name, bindings, body, span, env)
+            }
+            Call(_, callee, args, _) => return eval_call(callee, args, span, env),
+            Expansion(use_span, name, expr) => return eval_expansion(expr, name, *use_span, env),
+        }
+    }
+}
+
+// The forms below are evaluated by functions of their own, rather than
+// within `eval_step`. That keeps the stack frame of `eval_step`, which
+// every nested evaluation goes through, small.
+
+/// Look up the value of a symbol, which may name the export of a
+/// module
+///
+/// A symbol renamed by macro expansion which the expansion didn't bind
+/// refers to the binding of its original name in the top-level scope,
+/// where macros are defined, rather than to any local binding.
+fn eval_symbol(sym: &str, span: Span, env: &Rc<Environment>) -> EvalResult {
+    let value = env.get(sym).or_else(|| lookup_qualified(sym, env));
+    match (value, expand::original_name(sym)) {
+        (Some(value), _) => Ok(value),
+        (None, Some(name)) => eval_symbol(name, span, &env.top_level()),
+        (None, None) => {
+            Err(EvalError::from(EvalErrorKind::UndefinedSymbol(sym.into())).with_span(span))
+        }
+    }
+}
+
+/// Evaluate a `case` form, up to the tail of the matching clause
+fn eval_case(
+    key: &ast::Expr,
+    clauses: &[ast::CaseClause],
+    env: &Rc<Environment>,
+) -> Result<Step, EvalError> {
+    let key = eval_with_env(key, env)?;
+    for clause in clauses.iter() {
+        match clause {
+            ast::CaseClause::Data(_, _, data, _, body, _) => {
+                if data.iter().any(|datum| datum_to_value(datum).is_eq(&key)) {
+                    return eval_tail_body(body, env);
+                }
             }
+            ast::CaseClause::Else(_, _, body, _) => return eval_tail_body(body, env),
         }
     }
+    Ok(Step::Done(Value::Nil))
+}
+
+/// Bind `sym_tok` to the value of `value` in the current scope
+///
+/// A scope can only define each symbol once, unless it has been made
+/// redefinable. The REPL does this for its global scope, so that a
+/// definition can be re-entered, or a file loaded again, to update it.
+fn eval_define(
+    sym_tok: &ast::Token,
+    value: &ast::Expr,
+    env: &Rc<Environment>,
+) -> Result<Step, EvalError> {
+    let sym = to_sym(sym_tok)?;
+    if env.defines(&sym) && !env.is_redefinable() {
+        let kind = EvalErrorKind::AlreadyDefined(sym);
+        return Err(EvalError::from(kind).with_span(sym_tok.span()));
+    }
+    let value = eval_with_env(value, env)?;
+    env.define(sym, value.clone());
+    Ok(Step::Done(value))
+}
+
+/// Change the nearest existing binding of `sym_tok` to the value of
+/// `value`. Like `eval_symbol`, symbols renamed by macro expansion
+/// fall back to the top-level binding of their original name.
+fn eval_set(
+    sym_tok: &ast::Token,
+    value: &ast::Expr,
+    env: &Rc<Environment>,
+) -> Result<Step, EvalError> {
+    let sym = to_sym(sym_tok)?;
+    let value = eval_with_env(value, env)?;
+    let original = expand::original_name(&sym);
+    let assigned = env.set(&sym, value.clone())
+        || original.is_some_and(|name| env.top_level().set(name, value.clone()));
+    if !assigned {
+        let kind = EvalErrorKind::UndefinedSymbol(original.unwrap_or(&sym).into());
+        return Err(EvalError::from(kind).with_span(sym_tok.span()));
+    }
+    Ok(Step::Done(value))
+}
+
+/// Create a closure over `env`
+fn eval_lambda(params: &[ast::Token], body: &Rc<[ast::Expr]>, env: &Rc<Environment>) -> EvalResult {
+    let params = params.iter().map(to_sym).collect::<Result<Vec<_>, _>>()?;
+    Ok(Value::Lambda(Rc::new(Closure::new(
+        params,
+        Rc::clone(body),
+        Rc::clone(env),
+    ))))
+}
+
+/// Evaluate a `let` form. Every value is evaluated before any of the
+/// bindings are made.
+fn eval_let(
+    bindings: &ast::LetBindings,
+    body: &[ast::Expr],
+    env: &Rc<Environment>,
+) -> Result<Step, EvalError> {
+    let values = bindings
+        .bindings()
+        .iter()
+        .map(|binding| eval_with_env(binding.value(), env))
+        .collect::<Result<Vec<_>, _>>()?;
+    let scope = Environment::with_parent(env);
+    for (binding, value) in bindings.bindings().iter().zip(values) {
+        scope.define(to_sym(binding.name())?, value);
+    }
+    eval_tail_body(body, &scope)
+}
+
+/// Evaluate a `let*` form. Each binding gets a scope of its own, so
+/// closures created by earlier bindings can't see later ones.
+fn eval_let_star(
+    bindings: &ast::LetBindings,
+    body: &[ast::Expr],
+    env: &Rc<Environment>,
+) -> Result<Step, EvalError> {
+    let mut scope = Environment::with_parent(env);
+    for binding in bindings.bindings() {
+        let value = eval_with_env(binding.value(), &scope)?;
+        scope = Environment::with_parent(&scope);
+        scope.define(to_sym(binding.name())?, value);
+    }
+    eval_tail_body(body, &scope)
+}
+
+/// Evaluate a `letrec` or `letrec*` form. The bindings share one scope,
+/// so each value can refer to all of them. Both forms bind each value
+/// in turn, as `letrec*` does, so a value can use the ones before it.
+fn eval_letrec(
+    bindings: &ast::LetBindings,
+    body: &[ast::Expr],
+    env: &Rc<Environment>,
+) -> Result<Step, EvalError> {
+    let scope = Environment::with_parent(env);
+    for binding in bindings.bindings() {
+        let value = eval_with_env(binding.value(), &scope)?;
+        scope.define(to_sym(binding.name())?, value);
+    }
+    eval_tail_body(body, &scope)
+}
+
+/// Evaluate a named `let`, as a tail call to the function it binds
+fn eval_named_let(
+    name: &ast::Token,
+    bindings: &ast::LetBindings,
+    body: &Rc<[ast::Expr]>,
+    span: Span,
+    env: &Rc<Environment>,
+) -> Result<Step, EvalError> {
+    let params = bindings
+        .bindings()
+        .iter()
+        .map(|binding| to_sym(binding.name()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let args = bindings
+        .bindings()
+        .iter()
+        .map(|binding| eval_with_env(binding.value(), env))
+        .collect::<Result<Vec<_>, _>>()?;
+    let name = to_sym(name)?;
+    let scope = Environment::with_parent(env);
+    let closure = Rc::new(Closure::new(params, Rc::clone(body), Rc::clone(&scope)));
+    scope.define(name.clone(), Value::Lambda(Rc::clone(&closure)));
+    Ok(Step::Call(closure, args, name, span))
+}
+
+/// Evaluate the expression a use of the macro `name` at `span`
+/// expanded into. A call in tail position is still made from tail
+/// position, so errors within the function called aren't traced back
+/// to the expansion.
+fn eval_expansion(
+    expr: &ast::Expr,
+    name: &str,
+    span: Span,
+    env: &Rc<Environment>,
+) -> Result<Step, EvalError> {
+    eval_step(expr, env).map_err(|error| error.in_expansion(name, span))
+}
+
+/// Evaluate the function and arguments of a call, and then make the
+/// call from tail position
+fn eval_call(
+    callee: &ast::Expr,
+    args: &[ast::Expr],
+    span: Span,
+    env: &Rc<Environment>,
+) -> Result<Step, EvalError> {
+    let name = match *callee {
+        ast::Expr::Symbol(_, ref s) => expand::original_name(s).unwrap_or(s).into(),
+        _ => "<lambda>".into(),
+    };
+    let callee = eval_with_env(callee, env)?;
+    let args = args
+        .iter()
+        .map(|a| eval_with_env(a, env))
+        .collect::<Result<Vec<_>, _>>()?;
+    tail_call(callee, args, name, span)
+}
+
+/// Evaluate the clauses of a `cond` form in turn until one of their
+/// tests is true
+fn eval_cond(clauses: &[ast::CondClause], env: &Rc<Environment>) -> Result<Step, EvalError> {
+    for clause in clauses.iter() {
+        match clause {
+            ast::CondClause::Test(_, test, body, _) => {
+                let value = eval_with_env(test, env)?;
+                if value.is_truthy() {
+                    return match body.is_empty() {
+                        true => Ok(Step::Done(value)),
+                        false => eval_tail_body(body, env),
+                    };
+                }
+            }
+            ast::CondClause::Arrow(_, test, _, receiver, _) => {
+                let value = eval_with_env(test, env)?;
+                if value.is_truthy() {
+                    let name = match **receiver {
+                        ast::Expr::Symbol(_, ref s) => s.clone(),
+                        _ => "<lambda>".into(),
+                    };
+                    let function = eval_with_env(receiver, env)?;
+                    return tail_call(function, vec![value], name, receiver.span());
+                }
+            }
+            ast::CondClause::Else(_, _, body, _) => return eval_tail_body(body, env),
+        }
+    }
+    Ok(Step::Done(Value::Nil))
+}
+
+/// Call `function` with `args` from tail position. Calls to closures
+/// are returned to the trampoline rather than made straight away.
+fn tail_call(
+    function: Value,
+    args: Vec<Value>,
+    name: String,
+    span: Span,
+) -> Result<Step, EvalError> {
+    match function {
+        Value::Lambda(closure) => Ok(Step::Call(closure, args, name, span)),
+        other => apply(&other, args, &name, span).map(Step::Done),
+    }
 }
 
 /// Convert a token to a symbol.
-fn to_sym(token: ast::Token) -> Result<String, EvalError> {
-    match token.kind {
-        ast::TokenKind::Symbol(s) => Ok(s),
-        other => Err(EvalError(format!("Token '{:?}' is not symbol", other))),
+fn to_sym(token: &ast::Token) -> Result<String, EvalError> {
+    match &token.kind {
+        ast::TokenKind::Symbol(s) => Ok(s.clone()),
+        other => Err(EvalError::new(format!("{} is not a symbol", other))),
+    }
+}
+
+/// Convert a quoted datum into the value it represents
+pub(crate) fn datum_to_value(datum: &ast::Datum) -> Value {
+    match datum {
+        ast::Datum::Symbol(_, s) => Value::Symbol(s.as_str().into()),
+        ast::Datum::Number(_, n) => Value::Number(*n),
+        ast::Datum::String(_, s) => Value::String(s.as_str().into()),
+        ast::Datum::Bool(_, b) => Value::Bool(*b),
+        ast::Datum::List(_, items, _) => {
+            Value::list(items.iter().map(datum_to_value).collect::<Vec<_>>())
+        }
+        ast::Datum::Quote(_, datum) => {
+            Value::list(vec![Value::Symbol("quote".into()), datum_to_value(datum)])
+        }
     }
 }
 
@@ -130,70 +1251,953 @@ fn last_or_nil(values: Vec<Value>) -> Value {
     values.last().cloned().unwrap_or(Value::Nil)
 }
 
-/// Create the global environment. This is the root environment and
-/// has the builtin operators and functions defined in it.
-pub fn make_global_env() -> HashMap<String, Value> {
-    let mut env = HashMap::new();
+/// Combine a list of numbers with a checked arithmetic operator,
+/// starting from `initial`. Raises an overflow error if any step of
+/// the calculation overflows.
+fn fold_numbers(initial: i64, values: &[Value], op: fn(i64, i64) -> Option<i64>) -> EvalResult {
+    let mut acc = initial;
+    for value in values.iter() {
+        acc = op(acc, value.as_num()?).ok_or(EvalErrorKind::Overflow)?;
+    }
+    Ok(Value::Number(acc))
+}
 
-    env.insert(
-        "print".into(),
-        Value::Callable(|values| {
-            for value in values.iter() {
-                println!("{}", value);
-            }
-            Ok(last_or_nil(values))
-        }),
-    );
-    env.insert(
-        "exit".into(),
-        Value::Callable(|values| {
-            let status = values.into_iter().last().unwrap_or(Value::Number(0));
-            std::process::exit(status.into_num() as i32)
-        }),
-    );
-    env.insert(
-        "begin".into(),
-        Value::Callable(|values| Ok(last_or_nil(values))),
-    );
-    env.insert(
-        "+".into(),
-        Value::Callable(|values| Ok(Value::Number(values.iter().map(|i| i.into_num()).sum()))),
-    );
-    env.insert(
-        "*".into(),
-        Value::Callable(|values| Ok(Value::Number(values.iter().map(|i| i.into_num()).product()))),
-    );
-    env.insert(
-        "-".into(),
-        Value::Callable(|values| {
-            Ok(if let Some((first, rest)) = values.split_first() {
-                let first = first.into_num();
-                if rest.len() == 0 {
-                    Value::Number(-first)
-                } else {
-                    Value::Number(rest.iter().fold(first, |acc, n| acc - n.into_num()))
-                }
-            } else {
-                // (-) ~> 0 ; apparently
-                Value::Number(0)
-            })
-        }),
-    );
-    env.insert(
-        "/".into(),
-        Value::Callable(|values| {
-            if let Some((first, rest)) = values.split_first() {
-                let first = first.into_num();
-                Ok(if rest.len() == 0 {
-                    Value::Number(1 / first)
-                } else {
-                    Value::Number(rest.iter().fold(first, |acc, n| acc / n.into_num()))
-                })
+/// Create the global environment. This is an empty scope nested
+/// within a root prelude which has the builtin operators and functions
+/// defined in it.
+pub fn make_global_env() -> Rc<Environment> {
+    let prelude = Environment::new();
+    let global = Environment::with_parent(&prelude);
+
+    global
+        .modules()
+        .macros()
+        .set_environment(Rc::downgrade(&global));
+    prelude.define_native(module::make_load(Rc::downgrade(&global)));
+    prelude.define_native(module::make_read_file(Rc::downgrade(&global)));
+    prelude.define_native(NativeFunction::new("print", Arity::AtLeast(0), |values| {
+        for value in values.iter() {
+            println!("{}", value);
+        }
+        Ok(last_or_nil(values))
+    }));
+    prelude.define_native(NativeFunction::new(
+        "exit",
+        Arity::Between(0, 1),
+        |values| {
+            let status = match values.first() {
+                Some(status) => status.as_num()?,
+                None => 0,
+            };
+            let status = i32::try_from(status)
+                .map_err(|_| EvalError::new(format!("exit: status {} is out of range", status)))?;
+            std::process::exit(status)
+        },
+    ));
+    prelude.define_native(NativeFunction::new("begin", Arity::AtLeast(0), |values| {
+        Ok(last_or_nil(values))
+    }));
+    prelude.define_native(NativeFunction::new("+", Arity::AtLeast(0), |values| {
+        fold_numbers(0, &values, i64::checked_add)
+    }));
+    prelude.define_native(NativeFunction::new("*", Arity::AtLeast(0), |values| {
+        fold_numbers(1, &values, i64::checked_mul)
+    }));
+    prelude.define_native(NativeFunction::new("-", Arity::AtLeast(0), |values| {
+        match values.split_first() {
+            Some((first, [])) => first
+                .as_num()?
+                .checked_neg()
+                .map(Value::Number)
+                .ok_or_else(|| EvalErrorKind::Overflow.into()),
+            Some((first, rest)) => fold_numbers(first.as_num()?, rest, i64::checked_sub),
+            // (-) ~> 0 ; apparently
+            None => Ok(Value::Number(0)),
+        }
+    }));
+    prelude.define_native(NativeFunction::new("/", Arity::AtLeast(1), |values| {
+        let divide = |acc: i64, n: i64| {
+            if n == 0 {
+                Err(EvalErrorKind::DivisionByZero.into())
             } else {
-                Err(EvalError("Wrong number of arguments: /, 0".into()))
+                acc.checked_div(n)
+                    .ok_or_else(|| EvalErrorKind::Overflow.into())
+            }
+        };
+        match values.split_first() {
+            Some((first, [])) => divide(1, first.as_num()?).map(Value::Number),
+            Some((first, rest)) => {
+                let mut acc = first.as_num()?;
+                for n in rest.iter() {
+                    acc = divide(acc, n.as_num()?)?;
+                }
+                Ok(Value::Number(acc))
             }
-        }),
-    );
+            None => unreachable!("arity is checked before the call"),
+        }
+    }));
 
-    env
+    for &(name, op) in [
+        ("=", i64::eq as fn(&i64, &i64) -> bool),
+        ("<", i64::lt),
+        ("<=", i64::le),
+        (">", i64::gt),
+        (">=", i64::ge),
+    ]
+    .iter()
+    {
+        prelude.define_native(NativeFunction::new(
+            name,
+            Arity::AtLeast(1),
+            move |values| {
+                let numbers = values
+                    .iter()
+                    .map(Value::as_num)
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Value::Bool(numbers.windows(2).all(|w| op(&w[0], &w[1]))))
+            },
+        ));
+    }
+    prelude.define_native(NativeFunction::wrap("not", |value: Value| {
+        !value.is_truthy()
+    }));
+    prelude.define_native(NativeFunction::wrap("eq?", |a: Value, b: Value| {
+        a.is_eq(&b)
+    }));
+    prelude.define_native(NativeFunction::wrap("equal?", |a: Value, b: Value| {
+        a.is_equal(&b)
+    }));
+
+    prelude.define_native(NativeFunction::wrap("cons", Value::cons));
+    prelude.define_native(NativeFunction::wrap("car", |pair: Value| match pair {
+        Value::Pair(pair) => Ok(pair.0.clone()),
+        other => Err(EvalError::type_mismatch("pair", &other)),
+    }));
+    prelude.define_native(NativeFunction::wrap("cdr", |pair: Value| match pair {
+        Value::Pair(pair) => Ok(pair.1.clone()),
+        other => Err(EvalError::type_mismatch("pair", &other)),
+    }));
+    prelude.define_native(NativeFunction::new("list", Arity::AtLeast(0), |values| {
+        Ok(Value::list(values))
+    }));
+    prelude.define_native(NativeFunction::wrap("null?", |value: Value| {
+        matches!(value, Value::Nil)
+    }));
+    prelude.define_native(NativeFunction::wrap("pair?", |value: Value| {
+        matches!(value, Value::Pair(_))
+    }));
+    prelude.define_native(NativeFunction::wrap("length", |list: Value| {
+        Ok::<_, EvalError>(list.to_vec()?.len() as i64)
+    }));
+    prelude.define_native(NativeFunction::new(
+        "append",
+        Arity::AtLeast(0),
+        |mut values| {
+            // The last argument becomes the tail of the result as-is, so
+            // only the others need to be proper lists.
+            let mut result = values.pop().unwrap_or(Value::Nil);
+            for list in values.iter().rev() {
+                result = list
+                    .to_vec()?
+                    .into_iter()
+                    .rev()
+                    .fold(result, |tail, head| Value::cons(head, tail));
+            }
+            Ok(result)
+        },
+    ));
+    prelude.define_native(NativeFunction::wrap("reverse", |list: Value| {
+        Ok::<_, EvalError>(Value::list(list.to_vec()?.into_iter().rev()))
+    }));
+
+    prelude.define_native(NativeFunction::wrap("string-length", |s: Rc<str>| {
+        s.chars().count() as i64
+    }));
+    prelude.define_native(NativeFunction::new(
+        "substring",
+        Arity::Between(2, 3),
+        |values| {
+            let s = values[0].as_str()?;
+            let start = values[1].as_num()?;
+            let end = match values.get(2) {
+                Some(end) => end.as_num()?,
+                None => s.chars().count() as i64,
+            };
+            if start < 0 || end < start || end > s.chars().count() as i64 {
+                return Err(EvalError::new(format!(
+                    "substring: range {}..{} is out of bounds",
+                    start, end
+                )));
+            }
+            let sub: String = s
+                .chars()
+                .skip(start as usize)
+                .take((end - start) as usize)
+                .collect();
+            Ok(Value::String(sub.into()))
+        },
+    ));
+    prelude.define_native(NativeFunction::new(
+        "string-append",
+        Arity::AtLeast(0),
+        |values| {
+            let mut result = String::new();
+            for value in values.iter() {
+                result.push_str(value.as_str()?);
+            }
+            Ok(Value::String(result.into()))
+        },
+    ));
+    prelude.define_native(NativeFunction::wrap("string->number", |s: Rc<str>| {
+        s.parse::<i64>().ok()
+    }));
+    prelude.define_native(NativeFunction::wrap("number->string", |n: i64| {
+        n.to_string()
+    }));
+
+    global
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::super::parse;
+    use super::*;
+
+    fn run(source: &str) -> EvalResult {
+        eval_program(&parse::parse(source).unwrap(), &make_global_env())
+    }
+
+    fn run_num(source: &str) -> i64 {
+        run(source).unwrap().as_num().unwrap()
+    }
+
+    fn run_err(source: &str) -> EvalErrorKind {
+        run(source).unwrap_err().kind().clone()
+    }
+
+    fn run_str(source: &str) -> String {
+        match run(source) {
+            Ok(Value::String(s)) => s.to_string(),
+            other => panic!("expected string, found {:?}", other),
+        }
+    }
+
+    fn mismatch(expected: &'static str, found: &str) -> EvalErrorKind {
+        EvalErrorKind::TypeMismatch {
+            expected,
+            found: found.into(),
+        }
+    }
+
+    #[test]
+    fn eval_arithmetic() {
+        assert_eq!(0, run_num("(+)"));
+        assert_eq!(6, run_num("(+ 1 2 3)"));
+        assert_eq!(1, run_num("(*)"));
+        assert_eq!(24, run_num("(* 2 3 4)"));
+        assert_eq!(0, run_num("(-)"));
+        assert_eq!(-4, run_num("(- 4)"));
+        assert_eq!(3, run_num("(- 10 4 3)"));
+        assert_eq!(0, run_num("(/ 2)"));
+        assert_eq!(1, run_num("(/ 1)"));
+        assert_eq!(5, run_num("(/ 100 5 4)"));
+    }
+
+    #[test]
+    fn eval_arithmetic_errors() {
+        assert_eq!(mismatch("number", "string `a`"), run_err("(+ 1 \"a\")"));
+        assert_eq!(mismatch("number", "nil `nil`"), run_err("(* (begin) 2)"));
+        assert_eq!(mismatch("number", "string `a`"), run_err("(- \"a\")"));
+        assert_eq!(mismatch("number", "string `a`"), run_err("(/ 1 \"a\")"));
+        assert_eq!(EvalErrorKind::DivisionByZero, run_err("(/ 0)"));
+        assert_eq!(EvalErrorKind::DivisionByZero, run_err("(/ 1 0)"));
+        assert_eq!(EvalErrorKind::DivisionByZero, run_err("(/ 10 2 0)"));
+        assert_eq!(
+            EvalErrorKind::ArityMismatch {
+                name: "/".into(),
+                expected: Arity::AtLeast(1),
+                found: 0
+            },
+            run_err("(/)")
+        );
+    }
+
+    #[test]
+    fn eval_arithmetic_overflow() {
+        assert_eq!(
+            EvalErrorKind::Overflow,
+            run_err("(+ 9223372036854775807 1)")
+        );
+        assert_eq!(
+            EvalErrorKind::Overflow,
+            run_err("(* 9223372036854775807 2)")
+        );
+        assert_eq!(
+            EvalErrorKind::Overflow,
+            run_err("(- (- 0 9223372036854775807) 2)")
+        );
+        assert_eq!(
+            EvalErrorKind::Overflow,
+            run_err("(- (- (- 0 9223372036854775807) 1))")
+        );
+        assert_eq!(
+            EvalErrorKind::Overflow,
+            run_err("(/ (- (- 0 9223372036854775807) 1) (- 1))")
+        );
+    }
+
+    #[test]
+    fn eval_sequencing_builtins() {
+        assert_eq!(3, run_num("(begin 1 2 3)"));
+        assert!(matches!(run("(begin)"), Ok(Value::Nil)));
+        assert_eq!(2, run_num("(print 1 2)"));
+        assert_eq!(mismatch("number", "string `a`"), run_err("(exit \"a\")"));
+        assert_eq!(
+            EvalErrorKind::Custom("exit: status 4294967296 is out of range".into()),
+            run_err("(exit 4294967296)")
+        );
+        assert_eq!(
+            EvalErrorKind::ArityMismatch {
+                name: "exit".into(),
+                expected: Arity::Between(0, 1),
+                found: 2
+            },
+            run_err("(exit 1 2)")
+        );
+    }
+
+    #[test]
+    fn eval_string_builtins() {
+        assert_eq!(5, run_num("(string-length \"héllo\")"));
+        assert_eq!("éll", run_str("(substring \"héllo\" 1 4)"));
+        assert_eq!("llo", run_str("(substring \"héllo\" 2)"));
+        assert_eq!("foobar", run_str("(string-append \"foo\" \"\" \"bar\")"));
+        assert_eq!("", run_str("(string-append)"));
+        assert_eq!(-42, run_num("(string->number \"-42\")"));
+        assert!(matches!(run("(string->number \"x\")"), Ok(Value::Nil)));
+        assert_eq!("1337", run_str("(number->string 1337)"));
+    }
+
+    #[test]
+    fn eval_string_builtin_errors() {
+        assert_eq!(
+            mismatch("string", "number `1`"),
+            run_err("(string-length 1)")
+        );
+        assert_eq!(
+            mismatch("number", "string `1`"),
+            run_err("(substring \"abc\" \"1\")")
+        );
+        assert_eq!(
+            EvalErrorKind::Custom("substring: range 2..1 is out of bounds".into()),
+            run_err("(substring \"abc\" 2 1)")
+        );
+        assert_eq!(
+            EvalErrorKind::Custom("substring: range 0..4 is out of bounds".into()),
+            run_err("(substring \"abc\" 0 4)")
+        );
+        assert_eq!(
+            mismatch("string", "number `1`"),
+            run_err("(string-append \"a\" 1)")
+        );
+        assert_eq!(
+            mismatch("string", "number `1`"),
+            run_err("(string->number 1)")
+        );
+        assert_eq!(
+            mismatch("number", "string `1`"),
+            run_err("(number->string \"1\")")
+        );
+    }
+
+    fn run_bool(source: &str) -> bool {
+        match run(source) {
+            Ok(Value::Bool(b)) => b,
+            other => panic!("expected boolean, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn eval_truthiness() {
+        assert_eq!(1, run_num("(if #t 1 2)"));
+        assert_eq!(2, run_num("(if #f 1 2)"));
+        assert_eq!(2, run_num("(if (begin) 1 2)"));
+        assert_eq!(1, run_num("(if 0 1 2)"));
+        assert_eq!(1, run_num("(if \"\" 1 2)"));
+        assert_eq!(1, run_num("(if + 1 2)"));
+        assert!(run_bool("(not #f)"));
+        assert!(run_bool("(not (begin))"));
+        assert!(!run_bool("(not 0)"));
+        assert!(!run_bool("(not #t)"));
+    }
+
+    #[test]
+    fn eval_comparisons() {
+        assert!(run_bool("(= 1 1 1)"));
+        assert!(!run_bool("(= 1 1 2)"));
+        assert!(run_bool("(< 1 2 3)"));
+        assert!(!run_bool("(< 1 3 2)"));
+        assert!(run_bool("(<= 1 1 2)"));
+        assert!(run_bool("(> 3 2 1)"));
+        assert!(!run_bool("(> 3 3)"));
+        assert!(run_bool("(>= 3 3 1)"));
+        assert!(run_bool("(< 1)"));
+        assert_eq!(mismatch("number", "boolean `#t`"), run_err("(< 1 #t)"));
+        assert_eq!(
+            EvalErrorKind::ArityMismatch {
+                name: "=".into(),
+                expected: Arity::AtLeast(1),
+                found: 0
+            },
+            run_err("(=)")
+        );
+    }
+
+    #[test]
+    fn eval_equality() {
+        assert!(run_bool("(eq? 1 1)"));
+        assert!(!run_bool("(eq? 1 2)"));
+        assert!(run_bool("(eq? #f #f)"));
+        assert!(!run_bool("(eq? #f (begin))"));
+        assert!(run_bool("(eq? + +)"));
+        assert!(!run_bool("(eq? \"a\" \"a\")"));
+        assert!(run_bool("(define s \"a\") (eq? s s)"));
+        assert!(run_bool("(equal? \"a\" \"a\")"));
+        assert!(!run_bool("(equal? \"a\" \"b\")"));
+        assert!(!run_bool("(equal? 1 \"1\")"));
+    }
+
+    #[test]
+    fn eval_and_or() {
+        assert!(run_bool("(and)"));
+        assert!(!run_bool("(or)"));
+        assert_eq!(3, run_num("(and 1 2 3)"));
+        assert!(!run_bool("(and 1 #f 3)"));
+        assert_eq!(1, run_num("(or #f 1 2)"));
+        assert!(!run_bool("(or #f #f)"));
+        // Evaluation stops at the first value which decides the result
+        assert!(!run_bool("(and #f undefined)"));
+        assert_eq!(1, run_num("(or 1 undefined)"));
+        assert_eq!(
+            EvalErrorKind::UndefinedSymbol("undefined".into()),
+            run_err("(and #t undefined)")
+        );
+    }
+
+    fn run_display(source: &str) -> String {
+        run(source).unwrap().to_string()
+    }
+
+    #[test]
+    fn long_lists_dont_overflow_the_stack() {
+        let list = Value::list((0..1_000_000).map(Value::Number));
+        assert_eq!(1_000_000, list.to_vec().unwrap().len());
+        assert!(list.is_equal(&list.clone()));
+        assert_eq!(6_888_891, list.to_string().len());
+        drop(list);
+
+        // Replacing the last reference to a list drops it too
+        let env = make_global_env();
+        env.define("l".into(), Value::list((0..1_000_000).map(Value::Number)));
+        assert!(env.set("l", Value::Nil));
+    }
+
+    #[test]
+    fn eval_quote() {
+        assert_eq!("x", run_display("'x"));
+        assert_eq!("x", run_display("(quote x)"));
+        assert_eq!("(1 2 3)", run_display("'(1 2 3)"));
+        assert_eq!("(if #t \"a\" (b))", run_display("'(if #t \"a\" (b))"));
+        assert_eq!("(quote x)", run_display("''x"));
+        assert_eq!("nil", run_display("'()"));
+        assert!(matches!(run("'sym"), Ok(Value::Symbol(ref s)) if &**s == "sym"));
+        assert!(run_bool("(eq? 'a 'a)"));
+        assert!(run_bool("(equal? '(1 (2 \"x\")) (list 1 (list 2 \"x\")))"));
+        assert!(!run_bool("(eq? '(1) '(1))"));
+    }
+
+    #[test]
+    fn eval_pairs() {
+        assert_eq!("(1 . 2)", run_display("(cons 1 2)"));
+        assert_eq!("(1 2 . 3)", run_display("(cons 1 (cons 2 3))"));
+        assert_eq!("(1 2)", run_display("(cons 1 (cons 2 '()))"));
+        assert_eq!(1, run_num("(car '(1 2))"));
+        assert_eq!("(2)", run_display("(cdr '(1 2))"));
+        assert_eq!("nil", run_display("(cdr '(1))"));
+        assert_eq!("(1 \"two\" #f)", run_display("(list 1 \"two\" #f)"));
+        assert_eq!("nil", run_display("(list)"));
+        assert_eq!(mismatch("pair", "nil `nil`"), run_err("(car '())"));
+        assert_eq!(mismatch("pair", "number `1`"), run_err("(cdr 1)"));
+    }
+
+    #[test]
+    fn eval_list_predicates() {
+        assert!(run_bool("(null? '())"));
+        assert!(!run_bool("(null? '(1))"));
+        assert!(!run_bool("(null? 0)"));
+        assert!(run_bool("(pair? '(1))"));
+        assert!(run_bool("(pair? (cons 1 2))"));
+        assert!(!run_bool("(pair? '())"));
+        assert!(!run_bool("(pair? \"a\")"));
+    }
+
+    #[test]
+    fn eval_list_builtins() {
+        assert_eq!(0, run_num("(length '())"));
+        assert_eq!(3, run_num("(length '(1 (2 3) 4))"));
+        assert_eq!("(1 2 3 4)", run_display("(append '(1 2) '(3) '() '(4))"));
+        assert_eq!("(1 2 . 3)", run_display("(append '(1) '(2) 3)"));
+        assert_eq!("nil", run_display("(append)"));
+        assert_eq!("(3 2 1)", run_display("(reverse '(1 2 3))"));
+        assert_eq!("nil", run_display("(reverse '())"));
+        assert_eq!(
+            mismatch("list", "pair `(1 . 2)`"),
+            run_err("(length (cons 1 2))")
+        );
+        assert_eq!(mismatch("list", "number `1`"), run_err("(append 1 '(2))"));
+        assert_eq!(mismatch("list", "string `a`"), run_err("(reverse \"a\")"));
+    }
+
+    #[test]
+    fn eval_lambda_calls() {
+        assert_eq!(3, run_num("((lambda (x y) (+ x y)) 1 2)"));
+        assert_eq!(7, run_num("(define add (lambda (x y) (+ x y))) (add 3 4)"));
+        assert_eq!(
+            Err(EvalErrorKind::ArityMismatch {
+                name: "<lambda>".into(),
+                expected: Arity::Exact(2),
+                found: 1
+            }),
+            run("((lambda (x y) x) 1)")
+                .map_err(|e| e.kind().clone())
+                .map(|_| ())
+        );
+    }
+
+    #[test]
+    fn eval_closures_capture_environment() {
+        assert_eq!(
+            15,
+            run_num(
+                "(define make-adder (lambda (n) (lambda (x) (+ x n))))
+                 (define add5 (make-adder 5))
+                 (add5 10)"
+            )
+        );
+        // Parameters shadow outer bindings without modifying them
+        assert_eq!(
+            11,
+            run_num("(define x 1) (define f (lambda (x) (* x 10))) (+ (f x) x)")
+        );
+    }
+
+    #[test]
+    fn eval_let() {
+        assert_eq!(3, run_num("(let ((x 1) (y 2)) (+ x y))"));
+        assert_eq!(
+            1,
+            run_num("(define x 1) (let ((x 2) (y x)) (define z 3) y)")
+        );
+        assert_eq!(
+            EvalErrorKind::UndefinedSymbol("z".into()),
+            run_err("(let ((x 1)) (define z x)) z")
+        );
+        assert_eq!(4, run_num("(let* ((x 1) (x (+ x 1)) (y (* x 2))) y)"));
+        assert_eq!(
+            EvalErrorKind::UndefinedSymbol("x".into()),
+            run_err("(let* ((f (lambda () x)) (x 1)) (f))")
+        );
+        assert!(run_bool(
+            "(letrec ((even? (lambda (n) (if (= n 0) #t (odd? (- n 1)))))
+                      (odd? (lambda (n) (if (= n 0) #f (even? (- n 1))))))
+               (even? 10))"
+        ));
+        assert_eq!(
+            EvalErrorKind::UndefinedSymbol("b".into()),
+            run_err("(letrec* ((a b) (b 1)) a)")
+        );
+        // Named let loops are tail calls, so can run for as long as
+        // they like
+        assert_eq!(
+            5_000_050_000,
+            run_num("(let loop ((i 0) (acc 0)) (if (> i 100000) acc (loop (+ i 1) (+ acc i))))")
+        );
+        assert_eq!(
+            "(3 2 1)",
+            run_display("(let build ((n 3)) (if (= n 0) '() (cons n (build (- n 1)))))")
+        );
+    }
+
+    #[test]
+    fn eval_set() {
+        assert_eq!(2, run_num("(define x 1) (set! x 2) x"));
+        // Assignment changes the nearest binding, even from a closure
+        assert_eq!(
+            3,
+            run_num(
+                "(define count 0)
+                 (define tick (lambda () (set! count (+ count 1))))
+                 (tick) (tick) (tick) count"
+            )
+        );
+        assert_eq!(
+            "(10 1)",
+            run_display("(define x 1) (list (let ((x 2)) (set! x 10) x) x)")
+        );
+
+        let err = run("(define counter 0) (set! countr 1)").unwrap_err();
+        assert_eq!(&EvalErrorKind::UndefinedSymbol("countr".into()), err.kind());
+        assert_eq!(Some(Span::new(26, 32)), err.span());
+
+        let err = run("(define x 1) (define x 2)").unwrap_err();
+        assert_eq!(&EvalErrorKind::AlreadyDefined("x".into()), err.kind());
+        assert_eq!(Some(Span::new(22, 23)), err.span());
+        // Inner scopes can still shadow outer bindings
+        assert_eq!(2, run_num("(define x 1) ((lambda () (define x 2) x))"));
+        // Redefinable scopes replace their bindings
+        let env = make_global_env();
+        env.set_redefinable(true);
+        let program = parse::parse("(define x 1) (define x 2) x").unwrap();
+        assert_eq!(2, eval_program(&program, &env).unwrap().as_num().unwrap());
+        // Global definitions shadow builtins
+        assert_eq!(
+            "(1 2)",
+            run_display("(define reverse (lambda (l) l)) (reverse '(1 2))")
+        );
+        assert_eq!(3, run_num("(define length 3) length"));
+    }
+
+    #[test]
+    fn eval_conditionals() {
+        assert_eq!("nil", run_display("(if #f 1)"));
+        assert_eq!(1, run_num("(if #t 1)"));
+        let grade = "(define grade (lambda (n)
+                       (cond ((> n 89) 'a) ((> n 79) 'b) (else 'c))))";
+        assert_eq!(
+            "(a b c)",
+            run_display(&format!("{} (list (grade 95) (grade 80) (grade 3))", grade))
+        );
+        // A clause with no body returns the value of its test, and `=>`
+        // passes that value to a function
+        assert_eq!(7, run_num("(cond (#f 1) (7))"));
+        assert_eq!(10, run_num("(cond (5 => (lambda (x) (* x 2))) (else 0))"));
+        assert_eq!("nil", run_display("(cond (#f 1))"));
+        let classify = "(define classify (lambda (x)
+                          (case x ((1 2 3) 'small) ((big huge) 'large) (else 'unknown))))";
+        assert_eq!(
+            "(small large unknown)",
+            run_display(&format!(
+                "{} (list (classify 2) (classify 'huge) (classify \"big\"))",
+                classify
+            ))
+        );
+        assert_eq!("nil", run_display("(case 4 ((1) 'one))"));
+        assert_eq!(3, run_num("(define x 1) (when (= x 1) (set! x 2) (+ x 1))"));
+        assert_eq!("nil", run_display("(when #f 1)"));
+        assert_eq!(2, run_num("(unless #f 1 2)"));
+        assert_eq!("nil", run_display("(unless #t 1)"));
+        // Clause bodies are in tail position
+        assert_eq!(
+            0,
+            run_num(
+                "(define count (lambda (n) (cond ((= n 0) 0) (else (count (- n 1))))))
+                 (count 100000)"
+            )
+        );
+    }
+
+    #[test]
+    fn eval_modules() {
+        let module = "(module shapes (export square area)
+                        (define square (lambda (x) (* x x)))
+                        (define pi 3)
+                        (define area (lambda (r) (* pi (square r)))))";
+        assert_eq!(25, run_num(&format!("{} (shapes/square 5)", module)));
+        assert_eq!(12, run_num(&format!("{} (import shapes) (area 2)", module)));
+        assert_eq!(
+            9,
+            run_num(&format!(
+                "{} (import shapes (prefix s:)) (s:square 3)",
+                module
+            ))
+        );
+        assert_eq!(
+            27,
+            run_num(&format!(
+                "{} (import shapes (prefix s:) (rename (s:area circle))) (circle 3)",
+                module
+            ))
+        );
+        // Only exported bindings are visible outside the module
+        assert_eq!(
+            EvalErrorKind::UndefinedSymbol("pi".into()),
+            run_err(&format!("{} (import shapes) pi", module))
+        );
+        assert_eq!(
+            EvalErrorKind::UndefinedSymbol("shapes/pi".into()),
+            run_err(&format!("{} shapes/pi", module))
+        );
+        assert_eq!(
+            EvalErrorKind::Custom("module `shapes` doesn't export `volume`".into()),
+            run_err(&format!("{} (import shapes (rename (volume v)))", module))
+        );
+        assert_eq!(
+            EvalErrorKind::Custom("module `m` exports `x` but doesn't define it".into()),
+            run_err("(module m (export x) (define y 1))")
+        );
+        // Bindings from outside the module can't be exported
+        assert_eq!(
+            EvalErrorKind::Custom("module `m` exports `+` but doesn't define it".into()),
+            run_err("(module m (export +) (define y 1))")
+        );
+        // Only files can be module files, so these forms are evaluated
+        // at the top level
+        assert_eq!(5, run_num("(module k (export)) (define x 5) x"));
+        assert_eq!(
+            EvalErrorKind::UndefinedSymbol("k/x".into()),
+            run_err("(module k (export)) (define x 5) k/x")
+        );
+    }
+
+    #[test]
+    fn eval_macros() {
+        let swap = "(define-syntax swap!
+                      (syntax-rules ()
+                        ((_ a b) (begin (define tmp a) (set! a b) (set! b tmp)))))";
+        // The macro's `tmp` doesn't capture the one passed to it
+        assert_eq!(
+            "(2 1)",
+            run_display(&format!(
+                "{} (define tmp 1) (define y 2) (swap! tmp y) (list tmp y)",
+                swap
+            ))
+        );
+
+        let or = "(define-syntax my-or
+                    (syntax-rules ()
+                      ((_) #f)
+                      ((_ e) e)
+                      ((_ e rest ...) ((lambda (t) (if t t (my-or rest ...))) e))))";
+        assert_eq!(5, run_num(&format!("{} (define t 5) (my-or #f t)", or)));
+        let or2 = "(define-syntax or2
+                     (syntax-rules ()
+                       ((_ a b) (let ((t a)) (if t t b)))))";
+        assert_eq!(5, run_num(&format!("{} (define t 5) (or2 #f t)", or2)));
+        assert!(!run_bool(&format!("{} (my-or)", or)));
+
+        let let_ = "(define-syntax my-let
+                      (syntax-rules ()
+                        ((_ ((name value) ...) body ...) ((lambda (name ...) body ...) value ...))))";
+        assert_eq!(
+            3,
+            run_num(&format!("{} (my-let ((x 1) (y 2)) (+ x y))", let_))
+        );
+
+        // Literals must match exactly, and consecutive ellipses flatten
+        let flat = "(define-syntax flat
+                      (syntax-rules (in)
+                        ((_ in (x ...) ...) '(x ... ...))
+                        ((_ (x ...) ...) (list (list x ...) ...))))";
+        assert_eq!(
+            "(1 2 3 4)",
+            run_display(&format!("{} (flat in (1 2) (3 4))", flat))
+        );
+        assert_eq!(
+            "((1 2) (3))",
+            run_display(&format!("{} (flat (1 2) (3))", flat))
+        );
+        assert_eq!(
+            "(a (b c))",
+            run_display(
+                "(define-syntax pairs
+                   (syntax-rules ()
+                     ((_ (k v ...) ...) '((k (v ...)) ...))))
+                 (car (pairs (a b c)))"
+            )
+        );
+
+        // Quoted symbols in a template aren't renamed
+        assert_eq!(
+            "x",
+            run_display(
+                "(define-syntax q (syntax-rules () ((_) (lambda (x) 'x))))
+                 ((q) 1)"
+            )
+        );
+        assert_eq!(
+            "(x)",
+            run_display(
+                "(define-syntax q (syntax-rules () ((_) (lambda (x) (quote (x))))))
+                 ((q) 1)"
+            )
+        );
+        // Free symbols in a template refer to global bindings, even if
+        // the use is within a local binding of the same name
+        let first = "(define-syntax first (syntax-rules () ((_ l) (car l))))";
+        assert_eq!(
+            1,
+            run_num(&format!("{} (let ((car cdr)) (first '(1 2 3)))", first))
+        );
+        assert_eq!(
+            "(10 1)",
+            run_display(
+                "(define count 0)
+                 (define-syntax bump! (syntax-rules () ((_) (set! count (+ count 1)))))
+                 (list (let ((count 10)) (bump!) count) count)"
+            )
+        );
+        // Within a module they refer to the module's bindings
+        assert_eq!(
+            20,
+            run_num(
+                "(module m (export f)
+                   (define helper (lambda (x) (* x 10)))
+                   (define-syntax tenfold (syntax-rules () ((_ x) (helper x))))
+                   (define f (lambda (x) (tenfold x))))
+                 (m/f 2)"
+            )
+        );
+        // A template's `case` data are left as they are
+        assert_eq!(
+            "a",
+            run_display(
+                "(define-syntax is-a (syntax-rules () ((_ x) (case x ((a) 'a) (else 'b)))))
+                 (is-a 'a)"
+            )
+        );
+    }
+
+    #[test]
+    fn eval_defmacro() {
+        // Arguments are passed as data, and the result is evaluated
+        assert_eq!(
+            "(+ 1 2)",
+            run_display("(defmacro show (expr) (list 'quote expr)) (show (+ 1 2))")
+        );
+        assert_eq!(
+            6,
+            run_num(
+                "(defmacro reverse-call (f a b) (list f b a))
+                 (reverse-call - 2 8)"
+            )
+        );
+        // Expansion isn't hygienic, so macros can capture symbols
+        assert_eq!(
+            5,
+            run_num(
+                "(defmacro with-it (value body) (list (list 'lambda '(it) body) value))
+                 (with-it 5 it)"
+            )
+        );
+    }
+
+    #[test]
+    fn eval_recursion() {
+        assert_eq!(
+            120,
+            run_num(
+                "(define fact (lambda (n) (if (= n 0) 1 (* n (fact (- n 1))))))
+                 (fact 5)"
+            )
+        );
+    }
+
+    #[test]
+    fn eval_higher_order_functions() {
+        assert_eq!(
+            9,
+            run_num(
+                "(define twice (lambda (f x) (f (f x))))
+                 (twice (lambda (x) (* x 3)) 1)"
+            )
+        );
+    }
+
+    #[test]
+    fn eval_errors_carry_spans() {
+        let err = run("(+ 1 missing)").unwrap_err();
+        assert_eq!(
+            &EvalErrorKind::UndefinedSymbol("missing".into()),
+            err.kind()
+        );
+        assert_eq!(Some(Span::new(6, 13)), err.span());
+        assert!(err.trace().is_empty());
+
+        let err = run("(1 2)").unwrap_err();
+        assert_eq!(&EvalErrorKind::NotCallable("number `1`".into()), err.kind());
+        assert_eq!(Some(Span::new(1, 6)), err.span());
+    }
+
+    #[test]
+    fn eval_errors_trace_calls() {
+        let err = run("(define f (lambda (s) (string-length s)))
+(define g (lambda (x) (+ 1 (f x))))
+(g 1)")
+        .unwrap_err();
+        assert_eq!(
+            &EvalErrorKind::TypeMismatch {
+                expected: "string",
+                found: "number `1`".into()
+            },
+            err.kind()
+        );
+        assert_eq!(Some(Span::new(23, 40)), err.span());
+        assert_eq!(
+            vec!["f", "g"],
+            err.trace()
+                .iter()
+                .map(|f| f.name.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(Span::new(70, 75), err.trace()[0].span);
+        assert_eq!(Span::new(79, 84), err.trace()[1].span);
+
+        // Tail calls replace the caller's frame
+        let err = run("(define f (lambda (s) (string-length s)))
+(define g (lambda (x) (f x)))
+(g 1)")
+        .unwrap_err();
+        assert_eq!(
+            vec!["f"],
+            err.trace()
+                .iter()
+                .map(|f| f.name.as_str())
+                .collect::<Vec<_>>()
+        );
+
+        // Errors in an expansion are traced back to the macro's use
+        let err = run("(define-syntax inc (syntax-rules () ((_ x) (+ x 1))))
+(inc \"a\")")
+        .unwrap_err();
+        assert_eq!(Some(Span::new(44, 51)), err.span());
+        assert_eq!(
+            &[Frame {
+                kind: FrameKind::Expansion,
+                name: "inc".into(),
+                span: Span::new(55, 64)
+            }],
+            err.trace()
+        );
+    }
+
+    #[test]
+    fn eval_tail_calls() {
+        assert_eq!(
+            1_000_000,
+            run_num(
+                "(define count (lambda (n) (if (= n 1000000) n (count (+ n 1)))))
+                 (count 0)"
+            )
+        );
+        assert_eq!(
+            100_000,
+            run_num(
+                "(define even? (lambda (n) (if (= n 0) #t (odd? (- n 1)))))
+                 (define odd? (lambda (n) (and (not (= n 0)) (even? (- n 1)))))
+                 (define loop (lambda (n acc) (or (and (= n 0) acc) (loop (- n 1) (+ acc 1)))))
+                 (if (even? 100000) (loop 100000 0) 0)"
+            )
+        );
+    }
 }
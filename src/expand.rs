@@ -0,0 +1,784 @@
+//! Macro Expansion
+//!
+//! This module implements `syntax-rules` macros. A macro is a list of
+//! rules, each a pattern and a template. A use of the macro is read as
+//! a datum and matched against each pattern in turn. The template of
+//! the first rule which matches is then filled in with the parts of
+//! the use which the pattern variables matched.
+//!
+//! Expansions are made of the tokens of the template and of the use,
+//! so the spans of expanded code still point at the source text it
+//! came from.
+//!
+//! Expansion is hygienic. Each symbol in a template, other than the
+//! pattern variables and the syntax of the language, is given a fresh
+//! name each time the macro is expanded. Symbols the template binds
+//! with `lambda`, `define` or a `let` form therefore can't capture or
+//! clash with the symbols passed to the macro. The evaluator looks up
+//! a renamed symbol which the expansion doesn't bind by its original
+//! name in the top-level scope, the scope of the module the use is in
+//! or else the global scope, so local bindings around the use can't
+//! change what the template refers to. Symbols in quoted data are data
+//! rather than code, and are never renamed.
+//!
+//! Macros defined with `defmacro` are instead functions written in
+//! Formula One. They are called with the arguments of a use as quoted
+//! data, and the value they return is the code which replaces it. The
+//! code they return has no source text of its own, so every token in
+//! it refers to the whole use. These macros are not hygienic.
+
+use super::ast::{self, Datum, Expr, Token, TokenKind, Trivia, TriviaKind};
+use super::eval::{self, Closure, Environment, Value};
+use super::parse::{Diagnostic, ErrorCode, SPECIAL_FORMS};
+use codespan::Span;
+
+use std::collections::{HashMap, HashSet};
+use std::rc::{Rc, Weak};
+
+/// The symbol which marks a repeated pattern or template
+const ELLIPSIS: &str = "...";
+
+/// The pattern which matches anything without binding it
+const WILDCARD: &str = "_";
+
+/// Symbols which have a meaning within special forms, other than the
+/// names of the forms themselves
+const KEYWORDS: &[&str] = &[
+    "else",
+    "=>",
+    "export",
+    "prefix",
+    "rename",
+    "syntax-rules",
+    ELLIPSIS,
+    WILDCARD,
+];
+
+/// The macros defined so far
+///
+/// Macros are defined as their definitions are parsed, and can be
+/// used by any code parsed after that.
+///
+/// Procedural macros are evaluated in the global environment of the
+/// interpreter the macros belong to. Macros which don't belong to an
+/// interpreter create a global environment of their own when one is
+/// first needed.
+#[derive(Default)]
+pub struct Macros {
+    table: HashMap<String, Rc<Macro>>,
+    expansions: usize,
+    env: Weak<Environment>,
+    own_env: Option<Rc<Environment>>,
+}
+
+impl Macros {
+    /// Check if `name` is a macro
+    pub fn contains(&self, name: &str) -> bool {
+        self.table.contains_key(name)
+    }
+
+    /// Check if `sym` is part of the syntax of the language rather
+    /// than a variable: a special form, a keyword used within one, a
+    /// macro, or a literal matched by a macro's patterns
+    fn is_syntax(&self, sym: &str) -> bool {
+        let is_literal = |definition: &Macro| match definition {
+            Macro::Rules(rules) => rules.literals.iter().any(|literal| literal == sym),
+            Macro::Procedure(_) => false,
+        };
+        SPECIAL_FORMS.contains(&sym)
+            || KEYWORDS.contains(&sym)
+            || self.table.contains_key(sym)
+            || self.table.values().any(|definition| is_literal(definition))
+    }
+
+    /// Get the names of every macro, sorted
+    pub fn names(&self) -> Vec<String> {
+        let mut names = self.table.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// Add a macro, replacing any existing macro with the same name
+    pub fn define(&mut self, name: String, definition: Macro) {
+        self.table.insert(name, Rc::new(definition));
+    }
+
+    /// Add a procedural macro which expands uses by calling a function
+    /// with the given `params` and `body`
+    pub fn define_procedure(&mut self, name: String, params: Vec<String>, body: Rc<[Expr]>) {
+        let closure = Closure::new(params, body, self.environment());
+        self.define(name, Macro::Procedure(Value::Lambda(Rc::new(closure))));
+    }
+
+    /// Set the global environment procedural macros are evaluated in.
+    /// Only a weak reference is held, as the environment owns these
+    /// macros.
+    pub(crate) fn set_environment(&mut self, env: Weak<Environment>) {
+        self.env = env;
+    }
+
+    /// Get the environment procedural macros are evaluated in
+    fn environment(&mut self) -> Rc<Environment> {
+        match self.env.upgrade() {
+            Some(env) => env,
+            None => Rc::clone(self.own_env.get_or_insert_with(eval::make_global_env)),
+        }
+    }
+
+    /// Expand a use of a macro
+    ///
+    /// The use is the whole bracketed form, including the macro's name.
+    /// The expansion takes over the trivia around the use.
+    pub fn expand(&mut self, form: &Datum) -> Result<Datum, Diagnostic> {
+        let (name, args) = match form {
+            Datum::List(_, items, _) => match items.split_first() {
+                Some((Datum::Symbol(_, name), args)) => (name, args),
+                _ => return Err(invalid(form.span(), "expected a macro use".into())),
+            },
+            _ => return Err(invalid(form.span(), "expected a macro use".into())),
+        };
+        let definition = self
+            .table
+            .get(name)
+            .cloned()
+            .ok_or_else(|| invalid(form.span(), format!("`{}` is not a macro", name)))?;
+        self.expansions += 1;
+        let mut expansion = match &*definition {
+            Macro::Rules(rules) => rules.expand(name, args, form.span(), self.expansions, self)?,
+            Macro::Procedure(function) => call(function, name, args, form.span())?,
+        };
+        if let Datum::List(open, _, close) = form {
+            let first = first_token(&mut expansion);
+            *first = with_trivia(first, open.leading_trivia(), first.trailing_trivia());
+            let last = last_token(&mut expansion);
+            *last = with_trivia(last, last.leading_trivia(), close.trailing_trivia());
+        }
+        Ok(expansion)
+    }
+}
+
+/// A single macro definition
+pub enum Macro {
+    /// A macro defined by `syntax-rules`
+    Rules(SyntaxRules),
+    /// A macro defined by `defmacro`, and the function which expands
+    /// it
+    Procedure(Value),
+}
+
+/// The rules of a `syntax-rules` macro
+pub struct SyntaxRules {
+    literals: Vec<String>,
+    rules: Vec<Rule>,
+}
+
+/// A pattern and the template to expand when it matches, along with
+/// the symbols in the template which aren't pattern variables
+struct Rule {
+    pattern: Vec<Datum>,
+    template: Datum,
+    symbols: HashSet<String>,
+}
+
+/// The parts of a macro use matched by a pattern variable
+#[derive(Debug, Clone)]
+enum Binding {
+    /// A variable which isn't under an ellipsis matches one datum
+    One(Box<Datum>),
+    /// A variable under an ellipsis matches a datum for each time the
+    /// pattern was repeated
+    Many(Vec<Binding>),
+}
+
+/// The pattern variables bound by a successful match
+type Bindings = HashMap<String, Binding>;
+
+impl SyntaxRules {
+    /// Create a macro from its `(syntax-rules (<literal>...) (<pattern>
+    /// <template>)...)` specification
+    ///
+    /// Mistakes in the rules, such as misplaced ellipses or templates
+    /// which use pattern variables at the wrong depth, are reported
+    /// here rather than when the macro is used.
+    pub fn new(spec: &Datum) -> Result<Self, Diagnostic> {
+        let items = match spec {
+            Datum::List(_, items, _) => &items[..],
+            other => {
+                return Err(invalid(
+                    other.span(),
+                    "expected `(syntax-rules ...)`".into(),
+                ))
+            }
+        };
+        let (literals, rules) = match items {
+            [Datum::Symbol(_, keyword), Datum::List(_, literals, _), rules @ ..]
+                if keyword == "syntax-rules" =>
+            {
+                (literals, rules)
+            }
+            _ => {
+                return Err(invalid(
+                    spec.span(),
+                    "expected `(syntax-rules (<literal>...) <rule>...)`".into(),
+                ))
+            }
+        };
+        let literals = literals
+            .iter()
+            .map(|literal| match literal {
+                Datum::Symbol(_, s) if s != ELLIPSIS && s != WILDCARD => Ok(s.clone()),
+                other => Err(invalid(other.span(), "expected a literal symbol".into())),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut parsed = Vec::with_capacity(rules.len());
+        for rule in rules.iter() {
+            let (pattern, template) = match rule {
+                Datum::List(_, parts, _) => match &parts[..] {
+                    [Datum::List(_, pattern, _), template] => (pattern, template),
+                    _ => {
+                        return Err(invalid(
+                            rule.span(),
+                            "expected `(<pattern> <template>)`".into(),
+                        ))
+                    }
+                },
+                _ => {
+                    return Err(invalid(
+                        rule.span(),
+                        "expected `(<pattern> <template>)`".into(),
+                    ))
+                }
+            };
+            if !matches!(pattern.first(), Some(Datum::Symbol(..))) {
+                return Err(invalid(
+                    rule.span(),
+                    "patterns should start with the macro's name or `_`".into(),
+                ));
+            }
+            let mut vars = HashMap::new();
+            pattern_vars(&pattern[1..], &literals, 0, &mut vars)?;
+            check_template(template, &vars, 0)?;
+            let mut symbols = Vec::new();
+            template_vars(template, &mut symbols);
+            let symbols = symbols
+                .into_iter()
+                .filter(|symbol| !vars.contains_key(symbol))
+                .collect();
+            parsed.push(Rule {
+                pattern: pattern[1..].to_vec(),
+                template: template.clone(),
+                symbols,
+            });
+        }
+        Ok(SyntaxRules {
+            literals,
+            rules: parsed,
+        })
+    }
+
+    /// Expand a use of the macro `name` with the given `args`
+    ///
+    /// Each symbol of the template which isn't part of the syntax of
+    /// `macros` is renamed, with the `expansion` number making the new
+    /// names unique to this expansion.
+    fn expand(
+        &self,
+        name: &str,
+        args: &[Datum],
+        span: Span,
+        expansion: usize,
+        macros: &Macros,
+    ) -> Result<Datum, Diagnostic> {
+        for rule in self.rules.iter() {
+            let mut bindings = Bindings::new();
+            if match_list(&rule.pattern, args, &self.literals, &mut bindings) {
+                let renames = rule
+                    .symbols
+                    .iter()
+                    .filter(|symbol| !macros.is_syntax(symbol))
+                    .map(|symbol| (symbol.clone(), format!("{}#{}", symbol, expansion)))
+                    .collect();
+                return instantiate(&rule.template, &bindings, &renames, span);
+            }
+        }
+        Err(invalid(
+            span,
+            format!("no rule of the macro `{}` matches this use", name),
+        ))
+    }
+}
+
+/// Expand a use of the procedural macro `name` by calling its
+/// `function` with the `args` of the use as data
+fn call(function: &Value, name: &str, args: &[Datum], span: Span) -> Result<Datum, Diagnostic> {
+    let args = args.iter().map(eval::datum_to_value).collect();
+    let code = eval::apply(function, args, name, span).map_err(|error| {
+        let message = format!("the macro `{}` failed: {}", name, error.kind());
+        Diagnostic::new(ErrorCode::MacroFailed, span, message)
+    })?;
+    value_to_datum(&code, span).map_err(|value| {
+        let message = format!(
+            "the macro `{}` returned {} `{}`, which isn't code",
+            name,
+            value.type_name(),
+            value
+        );
+        Diagnostic::new(ErrorCode::MacroFailed, span, message)
+    })
+}
+
+/// Convert a value returned by a procedural macro into the code it
+/// represents. Every token refers to the `span` of the macro use.
+///
+/// Functions and improper lists can't be written as code, so the
+/// first one found is returned as the error.
+fn value_to_datum(value: &Value, span: Span) -> Result<Datum, Value> {
+    let token = |kind: TokenKind, text: String| Token::with_text(kind, span, text);
+    Ok(match value {
+        Value::Number(n) => Datum::Number(token(TokenKind::Number(*n), n.to_string()), *n),
+        Value::Bool(b) => Datum::Bool(
+            token(TokenKind::Bool(*b), if *b { "#t" } else { "#f" }.into()),
+            *b,
+        ),
+        Value::String(s) => Datum::String(
+            token(TokenKind::String(s.to_string()), ast::escape(s)),
+            s.to_string(),
+        ),
+        Value::Symbol(s) => Datum::Symbol(
+            token(TokenKind::Symbol(s.to_string()), s.to_string()),
+            s.to_string(),
+        ),
+        Value::Pair(_) | Value::Nil => {
+            let mut items = Vec::new();
+            let mut tail = value;
+            while let Value::Pair(pair) = tail {
+                if !items.is_empty() {
+                    let last = last_token(items.last_mut().unwrap());
+                    let space = Trivia::new(TriviaKind::Whitespace, span, " ");
+                    *last = with_trivia(last, last.leading_trivia(), &[space]);
+                }
+                items.push(value_to_datum(&pair.0, span)?);
+                tail = &pair.1;
+            }
+            if !matches!(tail, Value::Nil) {
+                return Err(value.clone());
+            }
+            Datum::List(
+                token(TokenKind::LeftBracket, "(".into()),
+                items,
+                token(TokenKind::RightBracket, ")".into()),
+            )
+        }
+        Value::Native(_) | Value::Lambda(_) => return Err(value.clone()),
+    })
+}
+
+/// Create an invalid macro diagnostic
+fn invalid(span: Span, message: String) -> Diagnostic {
+    Diagnostic::new(ErrorCode::InvalidMacro, span, message)
+}
+
+/// Check if `datum` is the ellipsis symbol
+fn is_ellipsis(datum: &Datum) -> bool {
+    matches!(datum, Datum::Symbol(_, s) if s == ELLIPSIS)
+}
+
+/// Count the ellipses at the start of `data`
+fn count_ellipses(data: &[Datum]) -> usize {
+    data.iter().take_while(|datum| is_ellipsis(datum)).count()
+}
+
+/// Collect the variables of a list of patterns, along with the number
+/// of ellipses each is nested under
+fn pattern_vars(
+    patterns: &[Datum],
+    literals: &[String],
+    depth: usize,
+    vars: &mut HashMap<String, usize>,
+) -> Result<(), Diagnostic> {
+    let mut seen_ellipsis = false;
+    for (index, pattern) in patterns.iter().enumerate() {
+        if is_ellipsis(pattern) {
+            if index == 0 || seen_ellipsis {
+                return Err(invalid(
+                    pattern.span(),
+                    "an ellipsis must follow a pattern, and can only be used once in each list"
+                        .into(),
+                ));
+            }
+            seen_ellipsis = true;
+            continue;
+        }
+        let depth = match patterns.get(index + 1) {
+            Some(next) if is_ellipsis(next) => depth + 1,
+            _ => depth,
+        };
+        match pattern {
+            Datum::Symbol(_, s) if s == WILDCARD || literals.contains(s) => (),
+            Datum::Symbol(_, s) => {
+                if vars.insert(s.clone(), depth).is_some() {
+                    return Err(invalid(
+                        pattern.span(),
+                        format!("the pattern variable `{}` is used more than once", s),
+                    ));
+                }
+            }
+            Datum::List(_, items, _) => pattern_vars(items, literals, depth, vars)?,
+            Datum::Quote(_, datum) => {
+                pattern_vars(std::slice::from_ref(datum), literals, depth, vars)?
+            }
+            Datum::Number(..) | Datum::String(..) | Datum::Bool(..) => (),
+        }
+    }
+    Ok(())
+}
+
+/// Check that each pattern variable in `template` is used under at
+/// least as many ellipses as it was matched with, and that each
+/// ellipsis follows a template which contains a repeated variable
+fn check_template(
+    template: &Datum,
+    vars: &HashMap<String, usize>,
+    depth: usize,
+) -> Result<(), Diagnostic> {
+    match template {
+        Datum::Symbol(_, s) => match vars.get(s) {
+            Some(&var_depth) if var_depth > depth => Err(invalid(
+                template.span(),
+                format!("the pattern variable `{}` must be followed by `...`", s),
+            )),
+            _ => Ok(()),
+        },
+        Datum::List(_, items, _) => {
+            let mut index = 0;
+            while index < items.len() {
+                let item = &items[index];
+                let ellipses = count_ellipses(&items[index + 1..]);
+                let valid = !is_ellipsis(item)
+                    && (ellipses == 0 || has_repeated_var(item, vars, depth + ellipses - 1));
+                if !valid {
+                    let ellipsis = &items[index + ellipses.max(1)];
+                    return Err(invalid(
+                        ellipsis.span(),
+                        "an ellipsis must follow a template containing a repeated pattern variable"
+                            .into(),
+                    ));
+                }
+                check_template(item, vars, depth + ellipses)?;
+                index += 1 + ellipses;
+            }
+            Ok(())
+        }
+        Datum::Quote(_, datum) => check_template(datum, vars, depth),
+        Datum::Number(..) | Datum::String(..) | Datum::Bool(..) => Ok(()),
+    }
+}
+
+/// Check if `template` uses a pattern variable matched under more
+/// than `depth` ellipses
+fn has_repeated_var(template: &Datum, vars: &HashMap<String, usize>, depth: usize) -> bool {
+    match template {
+        Datum::Symbol(_, s) => vars.get(s).is_some_and(|&d| d > depth),
+        Datum::List(_, items, _) => items.iter().any(|item| has_repeated_var(item, vars, depth)),
+        Datum::Quote(_, datum) => has_repeated_var(datum, vars, depth),
+        Datum::Number(..) | Datum::String(..) | Datum::Bool(..) => false,
+    }
+}
+
+/// Match a list of `patterns` against a list of `forms`
+fn match_list(
+    patterns: &[Datum],
+    forms: &[Datum],
+    literals: &[String],
+    bindings: &mut Bindings,
+) -> bool {
+    let ellipsis = patterns.iter().position(is_ellipsis);
+    let (before, repeated, after) = match ellipsis {
+        Some(index) => (
+            &patterns[..index - 1],
+            Some(&patterns[index - 1]),
+            &patterns[index + 1..],
+        ),
+        None => (patterns, None, &[][..]),
+    };
+    let repeated = match repeated {
+        None => {
+            return patterns.len() == forms.len()
+                && patterns
+                    .iter()
+                    .zip(forms)
+                    .all(|(pattern, form)| match_datum(pattern, form, literals, bindings))
+        }
+        Some(repeated) => repeated,
+    };
+    if forms.len() < before.len() + after.len() {
+        return false;
+    }
+    let (head, rest) = forms.split_at(before.len());
+    let (middle, tail) = rest.split_at(rest.len() - after.len());
+    if !match_list(before, head, literals, bindings) || !match_list(after, tail, literals, bindings)
+    {
+        return false;
+    }
+
+    // Each variable in the repeated pattern is bound to the list of
+    // everything it matched, even if it matched nothing.
+    let mut vars = HashMap::new();
+    let _ = pattern_vars(std::slice::from_ref(repeated), literals, 0, &mut vars);
+    let mut repeats = vars
+        .keys()
+        .map(|var| (var.clone(), Vec::new()))
+        .collect::<HashMap<_, _>>();
+    for form in middle.iter() {
+        let mut matched = Bindings::new();
+        if !match_datum(repeated, form, literals, &mut matched) {
+            return false;
+        }
+        for (var, binding) in matched {
+            repeats.entry(var).or_default().push(binding);
+        }
+    }
+    for (var, items) in repeats {
+        bindings.insert(var, Binding::Many(items));
+    }
+    true
+}
+
+/// Match a single `pattern` against a `form`
+fn match_datum(
+    pattern: &Datum,
+    form: &Datum,
+    literals: &[String],
+    bindings: &mut Bindings,
+) -> bool {
+    match (pattern, form) {
+        (Datum::Symbol(_, p), _) if p == WILDCARD => true,
+        (Datum::Symbol(_, p), Datum::Symbol(_, f)) if literals.contains(p) => p == f,
+        (Datum::Symbol(_, p), _) if literals.contains(p) => false,
+        (Datum::Symbol(_, p), _) => {
+            bindings.insert(p.clone(), Binding::One(Box::new(form.clone())));
+            true
+        }
+        (Datum::List(_, patterns, _), Datum::List(_, forms, _)) => {
+            match_list(patterns, forms, literals, bindings)
+        }
+        (Datum::Quote(_, p), Datum::Quote(_, f)) => match_datum(p, f, literals, bindings),
+        (Datum::Number(_, p), Datum::Number(_, f)) => p == f,
+        (Datum::String(_, p), Datum::String(_, f)) => p == f,
+        (Datum::Bool(_, p), Datum::Bool(_, f)) => p == f,
+        _ => false,
+    }
+}
+
+/// Fill in a template with the datums matched by the pattern
+/// variables in `bindings`, renaming the template's own symbols with
+/// `renames`. Quoted data in the template is data rather than code, so
+/// symbols in it are never renamed.
+fn instantiate(
+    template: &Datum,
+    bindings: &Bindings,
+    renames: &HashMap<String, String>,
+    span: Span,
+) -> Result<Datum, Diagnostic> {
+    match template {
+        Datum::Symbol(token, s) => match bindings.get(s) {
+            Some(Binding::One(datum)) => Ok(replace_trivia(datum, token)),
+            Some(Binding::Many(_)) => Err(invalid(
+                template.span(),
+                format!("the pattern variable `{}` must be followed by `...`", s),
+            )),
+            None => match renames.get(s) {
+                Some(renamed) => Ok(Datum::Symbol(rename(token, renamed), renamed.clone())),
+                None => Ok(template.clone()),
+            },
+        },
+        Datum::List(open, items, close) => {
+            let no_renames = HashMap::new();
+            let renames = match items.first() {
+                Some(Datum::Symbol(_, s)) if s == "quote" => &no_renames,
+                _ => renames,
+            };
+            let mut expanded = Vec::with_capacity(items.len());
+            let mut index = 0;
+            while index < items.len() {
+                let ellipses = count_ellipses(&items[index + 1..]);
+                let start = expanded.len();
+                instantiate_repeated(
+                    &items[index],
+                    ellipses,
+                    bindings,
+                    renames,
+                    span,
+                    &mut expanded,
+                )?;
+                index += 1 + ellipses;
+                // The last repetition takes the trivia after the ellipses
+                if let (true, Some(last), Datum::Symbol(ellipsis, _)) = (
+                    ellipses > 0 && expanded.len() > start,
+                    expanded.last_mut(),
+                    &items[index - 1],
+                ) {
+                    let token = last_token(last);
+                    *token = with_trivia(token, token.leading_trivia(), ellipsis.trailing_trivia());
+                }
+            }
+            Ok(Datum::List(open.clone(), expanded, close.clone()))
+        }
+        Datum::Quote(quote, datum) => Ok(Datum::Quote(
+            quote.clone(),
+            Box::new(instantiate(datum, bindings, &HashMap::new(), span)?),
+        )),
+        Datum::Number(..) | Datum::String(..) | Datum::Bool(..) => Ok(template.clone()),
+    }
+}
+
+/// Fill in a template which is followed by `ellipses` ellipses,
+/// appending each repetition to `expanded`. Each ellipsis repeats the
+/// template once for each item matched by one more level of pattern
+/// variables.
+fn instantiate_repeated(
+    template: &Datum,
+    ellipses: usize,
+    bindings: &Bindings,
+    renames: &HashMap<String, String>,
+    span: Span,
+    expanded: &mut Vec<Datum>,
+) -> Result<(), Diagnostic> {
+    if ellipses == 0 {
+        expanded.push(instantiate(template, bindings, renames, span)?);
+        return Ok(());
+    }
+    for bindings in repeat(template, bindings, span)? {
+        instantiate_repeated(template, ellipses - 1, &bindings, renames, span, expanded)?;
+    }
+    Ok(())
+}
+
+/// Get the bindings for each repetition of a template followed by an
+/// ellipsis. The repeated variables in the template must all have
+/// matched the same number of times.
+fn repeat(template: &Datum, bindings: &Bindings, span: Span) -> Result<Vec<Bindings>, Diagnostic> {
+    let mut vars = Vec::new();
+    template_vars(template, &mut vars);
+    let repeated = vars
+        .iter()
+        .filter_map(|var| match bindings.get(var) {
+            Some(Binding::Many(items)) => Some((var, items)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let count = repeated.first().map_or(0, |(_, items)| items.len());
+    if repeated.iter().any(|(_, items)| items.len() != count) {
+        return Err(invalid(
+            span,
+            "pattern variables repeated together matched different numbers of items".into(),
+        ));
+    }
+    Ok((0..count)
+        .map(|index| {
+            let mut bindings = bindings.clone();
+            for (var, items) in repeated.iter() {
+                bindings.insert((*var).clone(), items[index].clone());
+            }
+            bindings
+        })
+        .collect())
+}
+
+/// Collect every symbol in a template
+fn template_vars(template: &Datum, vars: &mut Vec<String>) {
+    match template {
+        Datum::Symbol(_, s) => vars.push(s.clone()),
+        Datum::List(_, items, _) => {
+            for item in items.iter() {
+                template_vars(item, vars);
+            }
+        }
+        Datum::Quote(_, datum) => template_vars(datum, vars),
+        Datum::Number(..) | Datum::String(..) | Datum::Bool(..) => (),
+    }
+}
+
+/// Get the name a template symbol had before it was renamed by macro
+/// expansion, or `None` if `sym` wasn't renamed
+pub(crate) fn original_name(sym: &str) -> Option<&str> {
+    let (name, expansion) = sym.rsplit_once('#')?;
+    let renamed =
+        !name.is_empty() && !expansion.is_empty() && expansion.bytes().all(|b| b.is_ascii_digit());
+    renamed.then_some(name)
+}
+
+/// Restore the original names of any renamed symbols in `datum`
+pub(crate) fn unrename(datum: Datum) -> Datum {
+    match datum {
+        Datum::Symbol(token, sym) => match original_name(&sym) {
+            Some(name) => Datum::Symbol(rename(&token, name), name.into()),
+            None => Datum::Symbol(token, sym),
+        },
+        Datum::List(open, items, close) => {
+            Datum::List(open, items.into_iter().map(unrename).collect(), close)
+        }
+        Datum::Quote(quote, datum) => Datum::Quote(quote, Box::new(unrename(*datum))),
+        other => other,
+    }
+}
+
+/// Copy a symbol token, giving it a new name
+fn rename(token: &Token, name: &str) -> Token {
+    Token::with_span(TokenKind::Symbol(name.into()), token.span())
+        .with_leading_trivia(token.leading_trivia().to_vec())
+        .with_trailing_trivia(token.trailing_trivia().to_vec())
+}
+
+/// Copy `datum` to stand in for the template symbol `token`
+///
+/// The copy takes the trivia around the symbol, so the whitespace and
+/// comments of the expansion follow the layout of the template.
+fn replace_trivia(datum: &Datum, token: &Token) -> Datum {
+    let mut datum = datum.clone();
+    let first = first_token(&mut datum);
+    *first = with_trivia(first, token.leading_trivia(), first.trailing_trivia());
+    let last = last_token(&mut datum);
+    *last = with_trivia(last, last.leading_trivia(), token.trailing_trivia());
+    datum
+}
+
+/// Copy `token` with new trivia
+fn with_trivia(token: &Token, leading: &[Trivia], trailing: &[Trivia]) -> Token {
+    Token::with_text(token.kind.clone(), token.span(), token.text())
+        .with_leading_trivia(leading.to_vec())
+        .with_trailing_trivia(trailing.to_vec())
+}
+
+/// Get the first token of `datum` in source order
+fn first_token(datum: &mut Datum) -> &mut Token {
+    match datum {
+        Datum::Symbol(token, _)
+        | Datum::Number(token, _)
+        | Datum::String(token, _)
+        | Datum::Bool(token, _)
+        | Datum::List(token, _, _)
+        | Datum::Quote(token, _) => token,
+    }
+}
+
+/// Get the last token of `datum` in source order
+fn last_token(datum: &mut Datum) -> &mut Token {
+    match datum {
+        Datum::Symbol(token, _)
+        | Datum::Number(token, _)
+        | Datum::String(token, _)
+        | Datum::Bool(token, _)
+        | Datum::List(_, _, token) => token,
+        Datum::Quote(_, datum) => last_token(datum),
+    }
+}
+
+/// Count the tokens of `datum`
+pub fn count_tokens(datum: &Datum) -> usize {
+    match datum {
+        Datum::Symbol(..) | Datum::Number(..) | Datum::String(..) | Datum::Bool(..) => 1,
+        Datum::List(_, items, _) => 2 + items.iter().map(count_tokens).sum::<usize>(),
+        Datum::Quote(_, datum) => 1 + count_tokens(datum),
+    }
+}
@@ -0,0 +1,222 @@
+//! Embedding Interface
+//!
+//! This module contains the `Interpreter`, which owns a global
+//! environment and evaluates programs in it. It is the main entry
+//! point for Rust code which wants to run Formula One programs.
+
+use super::ast;
+use super::eval::{self, Environment, EvalError, EvalResult, Value};
+use super::module::{self, Modules};
+use super::native::NativeFunction;
+use super::parse::{self, Diagnostic};
+use super::report;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Errors from running source text
+///
+/// Source can fail either when it is parsed, in which case all of the
+/// syntax errors are returned, or when it is evaluated.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// The source text contains syntax errors
+    Parse(Vec<Diagnostic>),
+    /// Evaluating the program failed
+    Eval(EvalError),
+}
+
+impl Error {
+    /// Render the error with snippets from the `source` it was raised
+    /// by. The `name` is used to refer to the source file.
+    pub fn render(&self, name: &str, source: &str) -> String {
+        let reports = match self {
+            Error::Parse(diagnostics) => diagnostics
+                .iter()
+                .map(|d| report::parse_report(d, source))
+                .collect::<Vec<_>>(),
+            Error::Eval(error) => vec![report::eval_report(error, source)],
+        };
+        report::render(name, source, &reports)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Parse(diagnostics) => {
+                let mut diagnostics = diagnostics.iter();
+                if let Some(first) = diagnostics.next() {
+                    write!(out, "{}", first)?;
+                }
+                for diagnostic in diagnostics {
+                    write!(out, "\n{}", diagnostic)?;
+                }
+                Ok(())
+            }
+            Error::Eval(error) => write!(out, "{}", error),
+        }
+    }
+}
+
+impl From<EvalError> for Error {
+    fn from(error: EvalError) -> Self {
+        Error::Eval(error)
+    }
+}
+
+/// Formula One Interpreter
+///
+/// Holds a global environment nested within a prelude of the builtin
+/// functions. Each program evaluated by the interpreter shares this
+/// environment, so definitions made by one program are visible to the
+/// next. Programs can define their own bindings with the same names as
+/// builtins, which shadow them.
+pub struct Interpreter {
+    env: Rc<Environment>,
+}
+
+impl Interpreter {
+    /// Create an interpreter with a fresh global environment
+    pub fn new() -> Self {
+        Interpreter {
+            env: eval::make_global_env(),
+        }
+    }
+
+    /// Evaluate a parsed program in the global environment
+    ///
+    /// Returns the value of the final top-level form, or `Nil` if the
+    /// program is empty. The program is only borrowed, so it can be
+    /// parsed once and then evaluated many times.
+    pub fn eval(&mut self, program: &ast::Program) -> EvalResult {
+        eval::eval_program(program, &self.env)
+    }
+
+    /// Parse the given source text, expanding any macros which have
+    /// been defined in the interpreter
+    ///
+    /// Macros the source defines are added to the interpreter, so are
+    /// available to later source.
+    pub fn parse(&mut self, source: &str) -> Result<ast::Program, Error> {
+        parse::parse_with_macros(source, &mut self.env.modules().macros()).map_err(Error::Parse)
+    }
+
+    /// Parse and evaluate the given source text
+    ///
+    /// Each top-level form is expanded and evaluated in turn, so
+    /// macros can call functions defined earlier in the source.
+    pub fn eval_str(&mut self, source: &str) -> Result<Value, Error> {
+        self.eval_source(None, source, |_| ())
+    }
+
+    /// Parse and evaluate source text read from the file at `path`
+    ///
+    /// Relative paths passed to `load` by the source are resolved
+    /// against the directory containing the file.
+    pub fn eval_file(&mut self, path: impl AsRef<Path>, source: &str) -> Result<Value, Error> {
+        self.eval_source(Some(path.as_ref()), source, |_| ())
+    }
+
+    /// Parse and evaluate source text, read from the file at `path` if
+    /// there is one
+    ///
+    /// `inspect` is called with each top-level form once its macros
+    /// have been expanded, just before it is evaluated.
+    pub fn eval_source(
+        &mut self,
+        path: Option<&Path>,
+        source: &str,
+        mut inspect: impl FnMut(&ast::Expr),
+    ) -> Result<Value, Error> {
+        module::eval_source(&self.env, path, source, &mut inspect)
+    }
+
+    /// Evaluate a program parsed from the file at `path`
+    ///
+    /// Like `eval_file`, relative paths passed to `load` by the program
+    /// are resolved against the directory containing the file.
+    pub fn eval_program_file(
+        &mut self,
+        path: impl AsRef<Path>,
+        program: &ast::Program,
+    ) -> EvalResult {
+        module::eval_program_file(&self.env, path.as_ref(), program)
+    }
+
+    /// Add a directory to the end of the search path for module files
+    ///
+    /// When a module is imported its file is looked for in the
+    /// directory of the importing file first, and then in each
+    /// directory on the search path in turn.
+    pub fn add_module_path(&mut self, dir: impl Into<PathBuf>) {
+        self.env.modules().add_search_path(dir.into());
+    }
+
+    /// Get the modules which have been defined or imported, and the
+    /// search path used to find module files
+    pub fn modules(&self) -> &Modules {
+        self.env.modules()
+    }
+
+    /// Set whether programs can define a global binding again,
+    /// replacing the earlier definition. By default this is an error,
+    /// and `set!` must be used to change a global's value.
+    pub fn allow_redefinition(&mut self, allow: bool) {
+        self.env.set_redefinable(allow);
+    }
+
+    /// Set the deepest that evaluation can nest before it is reported
+    /// as a "recursion too deep" error
+    ///
+    /// Tail calls don't count towards the limit. Each level of nesting
+    /// takes stack space, so the default of `DEFAULT_RECURSION_LIMIT`
+    /// suits the stack of a thread created with the default size.
+    /// Hosts which give the interpreter a larger stack can raise it.
+    pub fn set_recursion_limit(&mut self, limit: usize) {
+        self.env.modules().set_recursion_limit(limit);
+    }
+
+    /// Bind `name` to `value` in the global environment
+    pub fn define(&mut self, name: impl Into<String>, value: Value) {
+        self.env.define(name.into(), value);
+    }
+
+    /// Bind a native function in the global environment under its own
+    /// name
+    pub fn define_native(&mut self, function: NativeFunction) {
+        self.env.define_native(function);
+    }
+
+    /// Get the names of every macro defined in the interpreter, sorted
+    pub fn macros(&self) -> Vec<String> {
+        self.env.modules().macros().names()
+    }
+
+    /// Get every binding in the global environment, sorted by name.
+    /// Builtins are included unless a global binding shadows them.
+    pub fn globals(&self) -> Vec<(String, Value)> {
+        let mut globals = self
+            .env
+            .parent()
+            .map(|prelude| prelude.bindings())
+            .unwrap_or_default()
+            .into_iter()
+            .collect::<BTreeMap<_, _>>();
+        globals.extend(self.env.bindings());
+        globals.into_iter().collect()
+    }
+
+    /// Look up the value bound to `name` in the global environment
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.env.get(name)
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new()
+    }
+}
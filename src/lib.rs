@@ -0,0 +1,46 @@
+//! Formula One - An Experimental LISP
+//!
+//! This crate contains the parser and evaluator for the language. Source
+//! text is turned into a syntax tree with `parse`, which can then be
+//! evaluated by an `Interpreter`:
+//!
+//! ```
+//! use formula_one::{Interpreter, Value};
+//!
+//! let mut interpreter = Interpreter::new();
+//! match interpreter.eval_str("(define foo 1007) (+ foo 330)") {
+//!     Ok(Value::Number(n)) => assert_eq!(1337, n),
+//!     other => panic!("unexpected result {:?}", other),
+//! }
+//! ```
+//!
+//! Host programs can expose their own functions to scripts with
+//! `NativeFunction`.
+
+#![deny(missing_docs)]
+
+mod ast;
+mod eval;
+mod expand;
+mod interpreter;
+mod module;
+mod native;
+mod parse;
+mod read;
+mod report;
+mod syntax;
+
+pub use ast::{
+    escape, CaseClause, CondClause, Datum, Exports, Expr, ImportOption, LetBinding, LetBindings,
+    Program, Token, TokenKind, Trivia, TriviaKind,
+};
+pub use codespan::{ByteIndex, Span};
+pub use eval::{
+    Closure, Environment, EvalError, EvalErrorKind, EvalResult, Frame, FrameKind, Pair, Value,
+    DEFAULT_RECURSION_LIMIT,
+};
+pub use interpreter::{Error, Interpreter};
+pub use module::{Module, Modules};
+pub use native::{Arity, FromValue, IntoEvalResult, IntoNativeFunction, IntoValue, NativeFunction};
+pub use parse::{is_symbol_char, parse, tokenise, Diagnostic, ErrorCode, SPECIAL_FORMS};
+pub use read::read;
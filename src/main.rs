@@ -1,42 +1,220 @@
-#[deny(missing_docs)]
-mod ast;
-mod eval;
-mod parse;
+use formula_one::{Error, Interpreter, IntoValue, Value};
 
 use std::fs;
 use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+mod repl;
+
+/// Command line usage
+const USAGE: &str = "\
+Usage: formula-one [options] [file...] [-- arg...]
+       formula-one [options] --script <file> [arg...]
+
+Evaluates each file or expression in turn in a shared global environment,
+or starts the REPL if none are given.
+
+Options:
+  -e <expr>     evaluate an expression
+  -             read a script from standard input
+  --script <file>
+                evaluate the script file, and pass it every argument after it
+  -q, --quiet   don't print the value of each file or expression
+  --expand      print each form with its macros expanded before evaluating it
+  -I <dir>      add a directory to the module search path
+  --isolate     evaluate each input in its own global environment
+  -h, --help    show this help
+
+Arguments after `--` are available to scripts in the `command-line` list.
+Executable scripts can start with `#!/usr/bin/env -S formula-one --script`.
+Modules are also searched for in the directories listed in the
+`FORMULA_ONE_PATH` environment variable.";
+
+/// Environment variable holding extra module search directories
+const PATH_VAR: &str = "FORMULA_ONE_PATH";
+
+/// The size of the stack programs are evaluated on. This is enough for
+/// recursion to reach `RECURSION_LIMIT`, and be reported as an error,
+/// without overflowing the stack first, even in debug builds.
+const STACK_SIZE: usize = 64 * 1024 * 1024;
+
+/// The deepest evaluation can nest, which the large stack allows to be
+/// higher than the interpreter's default
+const RECURSION_LIMIT: usize = 1000;
+
+/// A source of code to evaluate from the command line
+enum Input {
+    /// An expression given with `-e`
+    Expr(String),
+    /// A script file
+    File(String),
+    /// A script read from standard input
+    Stdin,
+}
+
+/// Command line options
+struct Options {
+    inputs: Vec<Input>,
+    quiet: bool,
+    expand: bool,
+    isolate: bool,
+    module_path: Vec<PathBuf>,
+    script_args: Vec<String>,
+}
+
+impl Options {
+    /// Parse the command line arguments, not including the program
+    /// name. Returns `None` if only the usage should be shown.
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Option<Self>, String> {
+        let mut options = Options {
+            inputs: Vec::new(),
+            quiet: false,
+            expand: false,
+            isolate: false,
+            module_path: Vec::new(),
+            script_args: Vec::new(),
+        };
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-e" => match args.next() {
+                    Some(expr) => options.inputs.push(Input::Expr(expr)),
+                    None => return Err("`-e` needs an expression to evaluate".into()),
+                },
+                "-I" => match args.next() {
+                    Some(dir) => options.module_path.push(dir.into()),
+                    None => return Err("`-I` needs a directory to search".into()),
+                },
+                "-" => options.inputs.push(Input::Stdin),
+                "-q" | "--quiet" => options.quiet = true,
+                "--expand" => options.expand = true,
+                "--isolate" => options.isolate = true,
+                // The `#!` line of an executable script runs it with
+                // the script's path and then its arguments
+                "--script" => match args.next() {
+                    Some(path) => {
+                        options.inputs.push(Input::File(path));
+                        options.script_args.extend(args);
+                        break;
+                    }
+                    None => return Err("`--script` needs a script to run".into()),
+                },
+                "-h" | "--help" => return Ok(None),
+                "--" => {
+                    options.script_args.extend(args);
+                    break;
+                }
+                _ if arg.starts_with('-') => return Err(format!("unknown option `{}`", arg)),
+                _ => options.inputs.push(Input::File(arg)),
+            }
+        }
+        if let Some(path) = std::env::var_os(PATH_VAR) {
+            options.module_path.extend(std::env::split_paths(&path));
+        }
+        Ok(Some(options))
+    }
+
+    /// Create an interpreter set up with these options
+    fn new_interpreter(&self) -> Interpreter {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_recursion_limit(RECURSION_LIMIT);
+        for dir in self.module_path.iter() {
+            interpreter.add_module_path(dir.clone());
+        }
+        interpreter.define("command-line", self.script_args.clone().into_value());
+        interpreter
+    }
+}
 
 /// Main Entry Point
 ///
-/// Runs the REPL for the language
-fn main() {
-    let args = std::env::args();
-    if args.len() > 1 {
-        for arg in args.skip(1) {
-            let source = fs::read_to_string(&arg).expect("Could not read source file");
-            print(eval::eval(parse::parse(&source)));
-        }
-    } else {
-        let mut env = eval::make_global_env();
-        loop {
-            print(eval::eval_with_env(read(), &mut env));
+/// Runs the driver on a thread with a stack of `STACK_SIZE`.
+fn main() -> ExitCode {
+    std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(run)
+        .expect("could not start the interpreter thread")
+        .join()
+        .unwrap_or(ExitCode::FAILURE)
+}
+
+/// Run each of the inputs given on the command line, or the REPL if
+/// there are none. Inputs share one interpreter unless `--isolate` is
+/// given. Exits with a non-zero status if any input fails.
+fn run() -> ExitCode {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
         }
+        Err(message) => {
+            eprintln!("formula-one: {}\n\n{}", message, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    if options.inputs.is_empty() {
+        repl::run(|| options.new_interpreter());
+        return ExitCode::SUCCESS;
     }
+
+    let mut interpreter = options.new_interpreter();
+    for input in options.inputs.iter() {
+        let (name, source) = match read_input(input) {
+            Ok(input) => input,
+            Err(message) => {
+                eprintln!("formula-one: {}", message);
+                return ExitCode::FAILURE;
+            }
+        };
+        if options.isolate {
+            interpreter = options.new_interpreter();
+        }
+        let path = match input {
+            Input::File(path) => Some(Path::new(path)),
+            _ => None,
+        };
+        let result = interpreter.eval_source(path, &source, |form| {
+            if options.expand {
+                println!("{}", form.to_string().trim());
+            }
+        });
+        match result {
+            Ok(value) if !options.quiet => println!(" ~> {}", value),
+            Ok(_) => (),
+            Err(error) => {
+                eprint!("{}", error.render(&name, &source));
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    ExitCode::SUCCESS
 }
 
-/// Read the input string from source and parse it
-fn read() -> ast::Expr {
-    let mut buff = String::new();
-    print!("\u{1F3CE}  > ");
-    std::io::stdout().flush().unwrap();
-    std::io::stdin().read_line(&mut buff).unwrap();
-    parse::parse(&buff)
+/// Read the name and source text of an input
+fn read_input(input: &Input) -> Result<(String, String), String> {
+    match input {
+        Input::Expr(expr) => Ok(("<expr>".into(), expr.clone())),
+        Input::File(path) => fs::read_to_string(path)
+            .map(|source| (path.clone(), source))
+            .map_err(|error| format!("could not read `{}`: {}", path, error)),
+        Input::Stdin => {
+            let mut source = String::new();
+            std::io::stdin()
+                .read_to_string(&mut source)
+                .map(|_| ("<stdin>".into(), source))
+                .map_err(|error| format!("could not read standard input: {}", error))
+        }
+    }
 }
 
 /// Print out the result of an expression evaluation
-fn print(result: eval::EvalResult) {
+///
+/// Errors are rendered with snippets from the `source` file `name`
+fn print(name: &str, source: &str, result: Result<Value, Error>) {
     match result {
         Ok(value) => println!(" ~> {}", value),
-        Err(error) => println!(" !! {}", error),
+        Err(error) => print!("{}", error.render(name, source)),
     }
 }
@@ -0,0 +1,348 @@
+//! Modules and Source Files
+//!
+//! This module holds the state shared by everything which evaluates
+//! source files on behalf of a program: the table of defined modules,
+//! the macros defined so far, the files currently being evaluated, and
+//! the search path used to find module files. It is owned by the root
+//! environment so the evaluator can reach it from any scope.
+
+use super::ast;
+use super::eval::{self, Environment, EvalError, EvalResult, Value};
+use super::expand::Macros;
+use super::interpreter::Error;
+use super::native::{Arity, NativeFunction};
+use super::parse;
+use super::read;
+
+use std::cell::{Cell, RefCell, RefMut};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::{Rc, Weak};
+
+/// The file extension of module files
+const EXTENSION: &str = "f1";
+
+/// A module defined by a `module` form
+///
+/// Holds the scope the module's body was evaluated in and the names
+/// of the bindings it exports. Only exported bindings can be reached
+/// from outside the module.
+pub struct Module {
+    name: String,
+    env: Rc<Environment>,
+    exports: Vec<String>,
+}
+
+impl Module {
+    /// Create a module from the scope its body was evaluated in
+    pub(crate) fn new(name: String, env: Rc<Environment>, exports: Vec<String>) -> Self {
+        Module { name, env, exports }
+    }
+
+    /// Get the name of the module
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the names the module exports, in the order they were
+    /// declared
+    pub fn exports(&self) -> &[String] {
+        &self.exports
+    }
+
+    /// Look up the value of an exported binding. Only bindings made by
+    /// the module itself can be exported.
+    pub fn get(&self, sym: &str) -> Option<Value> {
+        if self.exports.iter().any(|export| export == sym) && self.env.defines(sym) {
+            self.env.get(sym)
+        } else {
+            None
+        }
+    }
+}
+
+/// The modules, macros and files known to a program, and the limit on
+/// how deeply its evaluation can recurse
+pub struct Modules {
+    table: RefCell<HashMap<String, Rc<Module>>>,
+    macros: RefCell<Macros>,
+    files: RefCell<Vec<PathBuf>>,
+    importing: RefCell<Vec<String>>,
+    search_path: RefCell<Vec<PathBuf>>,
+    recursion_limit: Cell<usize>,
+}
+
+impl Default for Modules {
+    fn default() -> Self {
+        Modules {
+            table: RefCell::default(),
+            macros: RefCell::default(),
+            files: RefCell::default(),
+            importing: RefCell::default(),
+            search_path: RefCell::default(),
+            recursion_limit: Cell::new(eval::DEFAULT_RECURSION_LIMIT),
+        }
+    }
+}
+
+impl Modules {
+    /// Look up a module which has been defined or imported
+    pub fn get(&self, name: &str) -> Option<Rc<Module>> {
+        self.table.borrow().get(name).cloned()
+    }
+
+    /// Add a module to the table, replacing any with the same name
+    pub(crate) fn register(&self, module: Module) {
+        self.table
+            .borrow_mut()
+            .insert(module.name.clone(), Rc::new(module));
+    }
+
+    /// Get the macros defined so far. Source text parsed on behalf of
+    /// the program expands these, and adds any it defines.
+    pub(crate) fn macros(&self) -> RefMut<'_, Macros> {
+        self.macros.borrow_mut()
+    }
+
+    /// Get the directories searched for module files, after the
+    /// directory of the importing file
+    pub fn search_path(&self) -> Vec<PathBuf> {
+        self.search_path.borrow().clone()
+    }
+
+    /// Add a directory to the end of the search path
+    pub fn add_search_path(&self, dir: PathBuf) {
+        self.search_path.borrow_mut().push(dir);
+    }
+
+    /// Get the deepest that non-tail evaluation can nest before it is
+    /// reported as recursing too deeply
+    pub fn recursion_limit(&self) -> usize {
+        self.recursion_limit.get()
+    }
+
+    /// Set the deepest that non-tail evaluation can nest
+    pub fn set_recursion_limit(&self, limit: usize) {
+        self.recursion_limit.set(limit);
+    }
+
+    /// Resolve `path` against the directory of the file currently
+    /// being evaluated, or the working directory if there isn't one
+    fn resolve(&self, path: &str) -> PathBuf {
+        match self.files.borrow().last().and_then(|file| file.parent()) {
+            Some(dir) => dir.join(path),
+            None => PathBuf::from(path),
+        }
+    }
+
+    /// Find the file for the module `name` on the search path
+    fn find(&self, name: &str) -> Option<PathBuf> {
+        let file = format!("{}.{}", name, EXTENSION);
+        std::iter::once(self.resolve(&file))
+            .chain(self.search_path.borrow().iter().map(|dir| dir.join(&file)))
+            .find(|path| path.is_file())
+    }
+}
+
+/// Evaluate `source` in `env`, one top-level form at a time
+///
+/// Each form is only expanded once the forms before it have been
+/// evaluated, so macros can call functions defined earlier in the same
+/// source. `inspect` is called with each form once it has been
+/// expanded, before it is evaluated.
+///
+/// Source read from the file at `path` is evaluated with the file on
+/// the stack of files being evaluated, and is a module file if it
+/// starts with a `module` form with no body.
+pub(crate) fn eval_source(
+    env: &Rc<Environment>,
+    path: Option<&Path>,
+    source: &str,
+    inspect: &mut dyn FnMut(&ast::Expr),
+) -> Result<Value, Error> {
+    let mut forms = parse::Forms::read(source).map_err(Error::Parse)?;
+    let path = match path {
+        Some(path) => fs::canonicalize(path).unwrap_or_else(|_| path.to_owned()),
+        None => return eval_forms(env, &mut forms, false, inspect),
+    };
+    let files = &env.modules().files;
+    files.borrow_mut().push(path);
+    let result = eval_forms(env, &mut forms, true, inspect);
+    files.borrow_mut().pop();
+    result
+}
+
+/// Evaluate each of the `forms` in turn, returning the value of the
+/// last, or `Nil` if there are none
+///
+/// If the forms are from a `file` which starts with a module header
+/// then the rest of them are evaluated as the module's body.
+fn eval_forms(
+    env: &Rc<Environment>,
+    forms: &mut parse::Forms,
+    file: bool,
+    inspect: &mut dyn FnMut(&ast::Expr),
+) -> Result<Value, Error> {
+    let mut scope = Rc::clone(env);
+    let mut header = None;
+    let mut result = Value::Nil;
+    let mut first = true;
+    while let Some(form) = next_form(env, forms)? {
+        inspect(&form);
+        if file && first && eval::is_module_header(&form) {
+            scope = Environment::for_module(env);
+            header = Some(form);
+        } else {
+            result = eval::eval_with_env(&form, &scope)?;
+        }
+        first = false;
+    }
+    match header {
+        Some(header) => Ok(eval::register_module(&header, scope)?),
+        None => Ok(result),
+    }
+}
+
+/// Expand and parse the next of the `forms`, with the macros defined
+/// so far
+fn next_form(env: &Rc<Environment>, forms: &mut parse::Forms) -> Result<Option<ast::Expr>, Error> {
+    let mut macros =
+        env.modules().macros.try_borrow_mut().map_err(|_| {
+            EvalError::new("source can't be evaluated while a macro is being expanded")
+        })?;
+    forms.next(&mut macros).transpose().map_err(Error::Parse)
+}
+
+/// Evaluate a `program`, parsed from the file at `path`, in `env`. The
+/// file is on the stack of files being evaluated while it runs, so
+/// that relative paths within it are resolved against its directory.
+pub(crate) fn eval_program_file(
+    env: &Rc<Environment>,
+    path: &Path,
+    program: &ast::Program,
+) -> EvalResult {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+    let files = &env.modules().files;
+    files.borrow_mut().push(path);
+    let result = eval::eval_file_program(program, env);
+    files.borrow_mut().pop();
+    result
+}
+
+/// Read and evaluate a file on behalf of a `load` or `import`
+///
+/// The spans of any errors refer to the file rather than the code
+/// which asked for it, so they are rendered into the message of a new
+/// error. Messages start with the name of the form, `what`, and refer
+/// to the file by the `name` it was asked for with.
+fn eval_nested(env: &Rc<Environment>, what: &str, name: &str, path: &Path) -> EvalResult {
+    let source = fs::read_to_string(path).map_err(|error| {
+        EvalError::new(format!("{}: could not read `{}`: {}", what, name, error))
+    })?;
+    eval_source(env, Some(path), &source, &mut |_| ()).map_err(|error| {
+        let rendered = error.render(&path.to_string_lossy(), &source);
+        EvalError::new(format!(
+            "{}: error in `{}`\n{}",
+            what,
+            name,
+            rendered.trim_end()
+        ))
+    })
+}
+
+/// Find the module `name`, evaluating its file if it hasn't been
+/// defined yet
+///
+/// Module files are named after the module, and are looked for in the
+/// directory of the importing file and then on the search path. A
+/// module which imports itself, directly or through other modules, is
+/// reported as an import cycle.
+pub(crate) fn import(env: &Rc<Environment>, name: &str) -> Result<Rc<Module>, EvalError> {
+    let modules = env.modules();
+    if let Some(module) = modules.get(name) {
+        return Ok(module);
+    }
+    if let Some(index) = modules.importing.borrow().iter().position(|m| m == name) {
+        let cycle = modules.importing.borrow()[index..].join(" -> ");
+        return Err(EvalError::new(format!(
+            "import: cycle {} -> {}",
+            cycle, name
+        )));
+    }
+    let path = modules
+        .find(name)
+        .ok_or_else(|| EvalError::new(format!("import: could not find module `{}`", name)))?;
+    modules.importing.borrow_mut().push(name.into());
+    let result = eval_nested(&env.global(), "import", name, &path);
+    modules.importing.borrow_mut().pop();
+    result?;
+    modules.get(name).ok_or_else(|| {
+        EvalError::new(format!(
+            "import: `{}` does not define the module `{}`",
+            path.display(),
+            name
+        ))
+    })
+}
+
+/// Create the `load` builtin
+///
+/// Loading a file evaluates it in the global environment `env`, and
+/// returns the value of its last form. Relative paths are resolved
+/// against the directory of the file doing the loading. The function
+/// only holds a weak reference to the environment it is bound in, so
+/// that the two don't keep each other alive.
+pub(crate) fn make_load(env: Weak<Environment>) -> NativeFunction {
+    NativeFunction::new("load", Arity::Exact(1), move |values| {
+        let name = match &values[0] {
+            Value::String(name) => name.clone(),
+            other => return Err(EvalError::type_mismatch("string", other)),
+        };
+        let env = env
+            .upgrade()
+            .ok_or_else(|| EvalError::new("load: the interpreter no longer exists"))?;
+        let modules = env.modules();
+        let path = modules.resolve(&name);
+        let canonical = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+        if modules.files.borrow().contains(&canonical) {
+            return Err(EvalError::new(format!(
+                "load: `{}` is already being loaded",
+                name
+            )));
+        }
+        eval_nested(&env, "load", &name, &path)
+    })
+}
+
+/// Create the `read-file` builtin
+///
+/// Reading a file returns a list of the data in it, as if each had been
+/// quoted, without evaluating any of them. Relative paths are resolved
+/// in the same way as for `load`.
+pub(crate) fn make_read_file(env: Weak<Environment>) -> NativeFunction {
+    NativeFunction::new("read-file", Arity::Exact(1), move |values| {
+        let name = match &values[0] {
+            Value::String(name) => name.clone(),
+            other => return Err(EvalError::type_mismatch("string", other)),
+        };
+        let env = env
+            .upgrade()
+            .ok_or_else(|| EvalError::new("read-file: the interpreter no longer exists"))?;
+        let path = env.modules().resolve(&name);
+        let source = fs::read_to_string(&path).map_err(|error| {
+            EvalError::new(format!("read-file: could not read `{}`: {}", name, error))
+        })?;
+        let data = read::read(&source).map_err(|diagnostics| {
+            let rendered = Error::Parse(diagnostics).render(&path.to_string_lossy(), &source);
+            EvalError::new(format!(
+                "read-file: error in `{}`\n{}",
+                name,
+                rendered.trim_end()
+            ))
+        })?;
+        Ok(Value::list(
+            data.iter().map(eval::datum_to_value).collect::<Vec<_>>(),
+        ))
+    })
+}
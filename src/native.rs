@@ -0,0 +1,344 @@
+//! Native Functions
+//!
+//! This module contains the interface used to expose functions
+//! written in Rust to Formula One programs. A `NativeFunction` wraps
+//! a boxed closure along with its name and arity, so host code can
+//! capture state such as counters or handles to external resources.
+//!
+//! The `FromValue` and `IntoValue` traits convert between `Value`s and
+//! Rust types. These allow plain Rust functions such as
+//! `fn(i64, i64) -> i64` to be registered directly with
+//! `NativeFunction::wrap`, without unpacking the argument list by
+//! hand.
+
+use super::eval::{EvalError, EvalErrorKind, EvalResult, Value};
+
+use std::fmt;
+use std::rc::Rc;
+
+/// The number of arguments a native function accepts
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Arity {
+    /// Exactly the given number of arguments
+    Exact(usize),
+    /// The given number of arguments or more
+    AtLeast(usize),
+    /// Between the two given numbers of arguments, inclusive
+    Between(usize, usize),
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Arity::Exact(n) => write!(out, "{}", n),
+            Arity::AtLeast(n) => write!(out, "at least {}", n),
+            Arity::Between(min, max) => write!(out, "between {} and {}", min, max),
+        }
+    }
+}
+
+impl Arity {
+    /// Check if a call with `count` arguments is allowed
+    pub fn accepts(&self, count: usize) -> bool {
+        match *self {
+            Arity::Exact(n) => count == n,
+            Arity::AtLeast(n) => count >= n,
+            Arity::Between(min, max) => count >= min && count <= max,
+        }
+    }
+}
+
+/// The boxed closure which implements a native function
+type NativeFn = Box<dyn Fn(Vec<Value>) -> EvalResult>;
+
+/// A function implemented in Rust
+///
+/// Native functions are called with the evaluated argument values
+/// once the number of arguments has been checked against the
+/// function's arity. The closure is only borrowed for each call, so
+/// any state it mutates should be held in a `Cell` or `RefCell`.
+pub struct NativeFunction {
+    name: String,
+    arity: Arity,
+    func: NativeFn,
+}
+
+impl NativeFunction {
+    /// Create a native function from a closure which accepts the raw
+    /// list of argument values
+    pub fn new<F>(name: impl Into<String>, arity: Arity, func: F) -> Self
+    where
+        F: Fn(Vec<Value>) -> EvalResult + 'static,
+    {
+        NativeFunction {
+            name: name.into(),
+            arity,
+            func: Box::new(func),
+        }
+    }
+
+    /// Create a native function from a Rust function or closure with
+    /// typed parameters. The arity is taken from the number of
+    /// parameters, and each argument is converted with `FromValue`.
+    pub fn wrap<Args>(name: impl Into<String>, func: impl IntoNativeFunction<Args>) -> Self {
+        func.into_native(name.into())
+    }
+
+    /// Get the name of the function
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the number of arguments the function accepts
+    pub fn arity(&self) -> Arity {
+        self.arity
+    }
+
+    /// Call the function with the given arguments
+    pub fn call(&self, args: Vec<Value>) -> EvalResult {
+        if !self.arity.accepts(args.len()) {
+            return Err(EvalErrorKind::ArityMismatch {
+                name: self.name.clone(),
+                expected: self.arity,
+                found: args.len(),
+            }
+            .into());
+        }
+        (self.func)(args)
+    }
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        out.debug_struct("NativeFunction")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish()
+    }
+}
+
+/// Conversion from an argument `Value` into a Rust type
+pub trait FromValue: Sized {
+    /// Convert the value, or return an error if it is the wrong type
+    fn from_value(value: Value) -> Result<Self, EvalError>;
+}
+
+/// Conversion from a Rust type into a `Value`
+pub trait IntoValue {
+    /// Convert into a value
+    fn into_value(self) -> Value;
+}
+
+/// Conversion from the return type of a native function into an
+/// evaluation result. This allows native functions to return either a
+/// plain value or a `Result`.
+pub trait IntoEvalResult {
+    /// Convert into an evaluation result
+    fn into_eval_result(self) -> EvalResult;
+}
+
+impl<T: IntoValue> IntoEvalResult for T {
+    fn into_eval_result(self) -> EvalResult {
+        Ok(self.into_value())
+    }
+}
+
+impl<T: IntoValue> IntoEvalResult for Result<T, EvalError> {
+    fn into_eval_result(self) -> EvalResult {
+        self.map(IntoValue::into_value)
+    }
+}
+
+impl FromValue for Value {
+    fn from_value(value: Value) -> Result<Self, EvalError> {
+        Ok(value)
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: Value) -> Result<Self, EvalError> {
+        match value {
+            Value::Number(n) => Ok(n),
+            other => Err(EvalError::type_mismatch("number", &other)),
+        }
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: Value) -> Result<Self, EvalError> {
+        match value {
+            Value::Bool(b) => Ok(b),
+            other => Err(EvalError::type_mismatch("boolean", &other)),
+        }
+    }
+}
+
+impl FromValue for Rc<str> {
+    fn from_value(value: Value) -> Result<Self, EvalError> {
+        match value {
+            Value::String(s) => Ok(s),
+            other => Err(EvalError::type_mismatch("string", &other)),
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: Value) -> Result<Self, EvalError> {
+        Rc::<str>::from_value(value).map(|s| s.to_string())
+    }
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Value {
+        self
+    }
+}
+
+impl IntoValue for i64 {
+    fn into_value(self) -> Value {
+        Value::Number(self)
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> Value {
+        Value::Bool(self)
+    }
+}
+
+impl IntoValue for Rc<str> {
+    fn into_value(self) -> Value {
+        Value::String(self)
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> Value {
+        Value::String(self.into())
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Value {
+        Value::String(self.into())
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self) -> Value {
+        Value::Nil
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> Value {
+        Value::list(self.into_iter().map(IntoValue::into_value))
+    }
+}
+
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> Value {
+        self.map(IntoValue::into_value).unwrap_or(Value::Nil)
+    }
+}
+
+/// Conversion of a Rust function with typed parameters into a native
+/// function. The `Args` type parameter is the tuple of parameter types
+/// and only serves to keep the implementations for each number of
+/// parameters apart.
+pub trait IntoNativeFunction<Args> {
+    /// Wrap the function as a native function called `name`
+    fn into_native(self, name: String) -> NativeFunction;
+}
+
+/// Implement `IntoNativeFunction` for functions with the given
+/// parameter types.
+macro_rules! impl_into_native {
+    ($count:expr; $($arg:ident),*) => {
+        impl<Func, Ret, $($arg),*> IntoNativeFunction<($($arg,)*)> for Func
+        where
+            Func: Fn($($arg),*) -> Ret + 'static,
+            Ret: IntoEvalResult,
+            $($arg: FromValue),*
+        {
+            #[allow(non_snake_case, unused_mut, unused_variables)]
+            fn into_native(self, name: String) -> NativeFunction {
+                NativeFunction::new(name, Arity::Exact($count), move |args| {
+                    let mut args = args.into_iter();
+                    $(let $arg = $arg::from_value(args.next().unwrap())?;)*
+                    self($($arg),*).into_eval_result()
+                })
+            }
+        }
+    };
+}
+
+impl_into_native!(0;);
+impl_into_native!(1; A);
+impl_into_native!(2; A, B);
+impl_into_native!(3; A, B, C);
+impl_into_native!(4; A, B, C, D);
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn wrap_typed_functions() {
+        let add = NativeFunction::wrap("add", |a: i64, b: i64| a + b);
+        assert_eq!(Arity::Exact(2), add.arity());
+        match add.call(vec![Value::Number(1), Value::Number(2)]) {
+            Ok(Value::Number(3)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(
+            Err(EvalErrorKind::ArityMismatch {
+                name: "add".into(),
+                expected: Arity::Exact(2),
+                found: 1
+            }
+            .into()),
+            add.call(vec![Value::Number(1)]).map(|_| ())
+        );
+        assert_eq!(
+            Err(EvalError::type_mismatch(
+                "number",
+                &Value::String("hello".into())
+            )),
+            add.call(vec![Value::Number(1), Value::String("hello".into())])
+                .map(|_| ())
+        );
+    }
+
+    #[test]
+    fn wrap_fallible_functions() {
+        let checked = NativeFunction::wrap("checked", |s: String| {
+            if s.is_empty() {
+                Err(EvalError::new("empty"))
+            } else {
+                Ok(s.len() as i64)
+            }
+        });
+        assert_eq!(
+            Err(EvalError::new("empty")),
+            checked.call(vec![Value::String("".into())]).map(|_| ())
+        );
+    }
+
+    #[test]
+    fn closures_keep_state() {
+        let count = Rc::new(Cell::new(0));
+        let counter = {
+            let count = Rc::clone(&count);
+            NativeFunction::wrap("counter", move || {
+                count.set(count.get() + 1);
+                count.get()
+            })
+        };
+        counter.call(Vec::new()).unwrap();
+        counter.call(Vec::new()).unwrap();
+        assert_eq!(2, count.get());
+    }
+}
@@ -1,15 +1,23 @@
 //! Syntax Parser
 //!
 //! The syntax parser is responsible for taking buffers of characters
-//! and returning structured syntax trees.
+//! and returning structured syntax trees. This happens in three
+//! stages: the tokeniser splits the text into tokens, the reader groups
+//! the tokens into data, and the syntax pass turns the data into
+//! expressions.
 
 use super::ast;
+use super::expand::Macros;
+use super::read::Reader;
+use super::syntax::Syntax;
 use codespan::*;
+use std::fmt;
 
 /// Tokenisation state
 ///
 /// Each variant represents a state in the DFA used by the tokeniser
 /// to recognise source text.
+#[derive(Copy, Clone)]
 enum TokeniseState {
     /// Initial token state. This is not a final state.
     Start,
@@ -17,33 +25,75 @@ enum TokeniseState {
     Lparen,
     /// Right parenthesis seen. This is a final state
     Rparen,
+    /// Quote seen. This is a final state
+    Quote,
     /// One or more digits seen. This is a final state.
     Number,
     /// One or more symbol characters seen. This is a final state.
     Symbol,
+    /// A `#` followed by zero or more symbol characters. This is a
+    /// final state, but only `#t` and `#f` are valid tokens.
+    Hash,
+    /// Inside a string literal. This is not a final state.
+    StringBody,
+    /// Backslash seen inside a string literal. This is not a final
+    /// state.
+    StringEscape,
+    /// Closing quote of a string literal seen. This is a final state.
+    StringEnd,
     /// Unicode whitespace characters
     Whitespace,
     /// Single line comment
     Comment,
+    /// A character which can't start any token. This is a final
+    /// state.
+    Error,
+}
+
+/// Check if `c` can appear in a symbol. Symbols can contain digits,
+/// but a token starting with one is a number.
+pub fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!$%&*+-./:<=>?@^_".contains(c)
 }
 
 /// Tokenise a given string
 ///
 /// Takes a given input string and transforms it into a vector of
-/// tokens by running a state machine over it.
-fn tokenise(source: &str) -> Vec<ast::Token> {
+/// tokens by running a state machine over it. The final token is
+/// always an end of file token.
+///
+/// A `#!` line at the very start of the source is treated as a
+/// comment, so scripts can be run directly with a shebang.
+pub fn tokenise(source: &str) -> Vec<ast::Token> {
     use TokeniseState::*;
 
     let mut result = Vec::new();
     let mut start = 0;
 
+    // Trivia is buffered up until we know which token it belongs
+    // to. The `previous` token is held back until the end of its line
+    // so that it can collect its trailing trivia.
+    let mut previous: Option<ast::Token> = None;
+    let mut leading = Vec::new();
+    let mut trailing = Vec::new();
+
+    if source.starts_with("#!") {
+        start = source.find(['\r', '\n']).unwrap_or(source.len());
+        let span = Span::new(1, (start as u32) + 1);
+        leading.push(ast::Trivia::new(
+            ast::TriviaKind::Comment,
+            span,
+            &source[..start],
+        ));
+    }
+
     loop {
         let mut state = Start;
         let mut end = start;
 
         // Search through the remaining characters until the state
         // machine can make no further transitions.
-        for c in source[start as usize..].chars() {
+        for c in source[start..].chars() {
             // This two-level match encodes the state transitions for
             // the automaton. First we dispatch based on the current
             // state, then the character we are looking at.
@@ -51,56 +101,30 @@ fn tokenise(source: &str) -> Vec<ast::Token> {
                 Start => match c {
                     '(' => Some(Lparen),
                     ')' => Some(Rparen),
+                    '\'' => Some(Quote),
                     '0'..='9' => Some(Number),
-                    'a'..='z'
-                    | 'A'..='Z'
-                    | '!'
-                    | '%'
-                    | '&'
-                    | '*'
-                    | '+'
-                    | '-'
-                    | '.'
-                    | '/'
-                    | ':'
-                    | '<'
-                    | '='
-                    | '>'
-                    | '?'
-                    | '@'
-                    | '$'
-                    | '^' => Some(Symbol),
+                    '"' => Some(StringBody),
+                    '#' => Some(Hash),
+                    c if is_symbol_char(c) => Some(Symbol),
                     ';' => Some(Comment),
                     c if c.is_whitespace() => Some(Whitespace),
-                    _ => None,
+                    _ => Some(Error),
                 },
-                Lparen | Rparen => None,
+                Lparen | Rparen | Quote | StringEnd | Error => None,
                 Number => match c {
                     '0'..='9' => Some(Number),
                     _ => None,
                 },
-                Symbol => match c {
-                    'A'..='Z'
-                    | 'a'..='z'
-                    | '!'
-                    | '%'
-                    | '&'
-                    | '*'
-                    | '+'
-                    | '-'
-                    | '.'
-                    | '/'
-                    | ':'
-                    | '<'
-                    | '='
-                    | '>'
-                    | '?'
-                    | '@'
-                    | '$'
-                    | '^'
-                    | '0'..='9' => Some(Symbol),
+                Symbol | Hash => match c {
+                    c if is_symbol_char(c) => Some(state),
                     _ => None,
                 },
+                StringBody => match c {
+                    '"' => Some(StringEnd),
+                    '\\' => Some(StringEscape),
+                    _ => Some(StringBody),
+                },
+                StringEscape => Some(StringBody),
                 Whitespace => {
                     if c.is_whitespace() {
                         Some(Whitespace)
@@ -127,115 +151,364 @@ fn tokenise(source: &str) -> Vec<ast::Token> {
             }
         }
 
+        let token_start = start;
         let token_str = &source[start..end];
         let span = Span::new((start as u32) + 1, (end as u32) + 1);
 
         start = end;
 
-        // all our states are accepting other than `Start` and
-        // `Whitespace`. Choose the token kind based on the state we
-        // have landed in.
+        // all our states are accepting other than `Start`. Choose the
+        // token kind based on the state we have landed in. Whitespace
+        // and comments are attached to the surrounding tokens as
+        // trivia.
         let kind = match state {
             // If no transition was followed from the start state we
             // have completed tokenisation
             Start => break,
             Lparen => ast::TokenKind::LeftBracket,
             Rparen => ast::TokenKind::RightBracket,
-            Number => ast::TokenKind::Number(token_str.parse().unwrap()),
+            Quote => ast::TokenKind::Quote,
+            // Literals too large to fit in an `i64` are reported by
+            // the parser as invalid tokens.
+            Number => token_str
+                .parse()
+                .map(ast::TokenKind::Number)
+                .unwrap_or(ast::TokenKind::Error),
             Symbol => ast::TokenKind::Symbol(token_str.into()),
-            // Skip whitespace for now
-            Whitespace | Comment => continue,
+            Hash => match token_str {
+                "#t" => ast::TokenKind::Bool(true),
+                "#f" => ast::TokenKind::Bool(false),
+                _ => ast::TokenKind::Error,
+            },
+            // Strings with invalid escapes, or which are never closed,
+            // are also reported by the parser as invalid tokens.
+            StringEnd => unescape(token_str)
+                .map(ast::TokenKind::String)
+                .unwrap_or(ast::TokenKind::Error),
+            StringBody | StringEscape | Error => ast::TokenKind::Error,
+            Comment => {
+                let trivia = ast::Trivia::new(ast::TriviaKind::Comment, span, token_str);
+                if previous.is_some() {
+                    trailing.push(trivia);
+                } else {
+                    leading.push(trivia);
+                }
+                continue;
+            }
+            Whitespace => {
+                match (previous.take(), token_str.find('\n')) {
+                    // The line ending finishes the previous token's
+                    // trailing trivia. Any whitespace after it leads
+                    // the next token.
+                    (Some(token), Some(newline)) => {
+                        let split = token_start + newline + 1;
+                        let span = Span::new((token_start as u32) + 1, (split as u32) + 1);
+                        trailing.push(ast::Trivia::new(
+                            ast::TriviaKind::Whitespace,
+                            span,
+                            &source[token_start..split],
+                        ));
+                        result.push(token.with_trailing_trivia(std::mem::take(&mut trailing)));
+                        if split < end {
+                            let span = Span::new((split as u32) + 1, (end as u32) + 1);
+                            leading.push(ast::Trivia::new(
+                                ast::TriviaKind::Whitespace,
+                                span,
+                                &source[split..end],
+                            ));
+                        }
+                    }
+                    (Some(token), None) => {
+                        trailing.push(ast::Trivia::new(
+                            ast::TriviaKind::Whitespace,
+                            span,
+                            token_str,
+                        ));
+                        previous = Some(token);
+                    }
+                    (None, _) => leading.push(ast::Trivia::new(
+                        ast::TriviaKind::Whitespace,
+                        span,
+                        token_str,
+                    )),
+                }
+                continue;
+            }
         };
 
-        result.push(ast::Token::with_span(kind, span));
+        if let Some(token) = previous.take() {
+            result.push(token.with_trailing_trivia(std::mem::take(&mut trailing)));
+        }
+        previous = Some(
+            ast::Token::with_text(kind, span, token_str)
+                .with_leading_trivia(std::mem::take(&mut leading)),
+        );
+    }
+
+    if let Some(token) = previous {
+        result.push(token.with_trailing_trivia(trailing));
     }
 
+    // The token stream is always terminated by an end of file
+    // token. This holds any trivia after the final line of tokens.
+    let end = (source.len() as u32) + 1;
+    result.push(
+        ast::Token::with_text(ast::TokenKind::EndOfFile, Span::new(end, end), "")
+            .with_leading_trivia(leading),
+    );
+
     result
 }
 
-/// Parser state structure
+/// Description of a string literal with no closing quote
+const UNTERMINATED_STRING: &str = "unterminated string literal";
+
+/// Decode a string literal
 ///
-/// Contains the lookahead inforation for the parser
-struct ParseState<I: Iterator<Item = ast::Token>>(std::iter::Peekable<I>);
-
-impl<I> ParseState<I>
-where
-    I: Iterator<Item = ast::Token>,
-{
-    /// Pase a single form from a list of tokens
-    fn parse_expr(&mut self) -> ast::Expr {
-        if let Some(token) = self.0.next() {
-            use ast::TokenKind::*;
-            match token.kind {
-                LeftBracket => self.parse_form(token),
-                RightBracket => panic!("unexpected token!"),
-                Number(n) => ast::Expr::Number(token, n),
-                Symbol(ref s) => {
-                    let sym = s.clone();
-                    ast::Expr::Symbol(token, sym)
-                }
-            }
-        } else {
-            panic!("invalid expression.")
+/// Takes the source text of a string literal, including its quotes,
+/// and returns the string it represents with any escape sequences
+/// replaced. If the literal is invalid a description of the problem
+/// is returned instead.
+fn unescape(literal: &str) -> Result<String, String> {
+    let mut chars = literal.chars().skip(1);
+    let mut result = String::new();
+    loop {
+        match chars.next() {
+            None => return Err(UNTERMINATED_STRING.into()),
+            Some('"') => return Ok(result),
+            Some('\\') => result.push(match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('"') => '"',
+                Some('\\') => '\\',
+                Some('u') => unescape_unicode(&mut chars)?,
+                Some(c) => return Err(format!("unknown escape sequence `\\{}`", c)),
+                None => return Err(UNTERMINATED_STRING.into()),
+            }),
+            Some(c) => result.push(c),
         }
     }
+}
 
-    // Parse one of our recognised strucutred forms beginning with the
-    // given token
-    fn parse_form(&mut self, open: ast::Token) -> ast::Expr {
-        use ast::TokenKind::*;
-        match self.0.peek() {
-            Some(&ast::Token {
-                kind: Symbol(ref sym),
-                ..
-            }) => match &sym[..] {
-                "if" => {
-                    let if_tok = self.0.next().unwrap();
-                    let cond = self.parse_expr();
-                    let if_true = self.parse_expr();
-                    let if_false = self.parse_expr();
-                    let close = self.0.next().unwrap();
-                    ast::Expr::If(
-                        open,
-                        if_tok,
-                        Box::new(cond),
-                        Box::new(if_true),
-                        Box::new(if_false),
-                        close,
-                    )
-                }
-                "define" => {
-                    let define_tok = self.0.next().unwrap();
-                    let sym_tok = self.0.next().unwrap();
-                    let value = self.parse_expr();
-                    let close = self.0.next().unwrap();
-                    ast::Expr::Define(open, define_tok, sym_tok, Box::new(value), close)
-                }
-                _ => {
-                    let sym_tok = self.0.next().unwrap();
-                    let mut args = Vec::new();
-                    while let Some(token) = self.0.peek() {
-                        if token.kind == RightBracket {
-                            break;
-                        }
-                        args.push(self.parse_expr());
-                    }
-                    let close = self.0.next().unwrap();
-                    ast::Expr::Call(open, sym_tok, args, close)
-                }
-            },
-            _ => panic!("invalid expression"),
+/// Decode the `{XXXX}` part of a `\u{XXXX}` unicode escape
+fn unescape_unicode(chars: &mut impl Iterator<Item = char>) -> Result<char, String> {
+    let invalid = || String::from("invalid unicode escape, expected `\\u{XXXX}`");
+    if chars.next() != Some('{') {
+        return Err(invalid());
+    }
+    let mut digits = String::new();
+    loop {
+        match chars.next() {
+            Some('}') => break,
+            Some(c) if c.is_ascii_hexdigit() && digits.len() < 6 => digits.push(c),
+            _ => return Err(invalid()),
         }
     }
+    u32::from_str_radix(&digits, 16)
+        .ok()
+        .and_then(std::char::from_u32)
+        .ok_or_else(|| format!("`\\u{{{}}}` is not a valid unicode character", digits))
 }
 
-/// Parse source text into a structured AST expression
+/// Describe why the tokeniser couldn't recognise the given text
 ///
-/// This first tokenises the source text and then parses the resulting
-/// list of tokens into a single expression form.
-pub fn parse(source: &str) -> ast::Expr {
-    let tokens = tokenise(source);
-    ParseState(tokens.into_iter().peekable()).parse_expr()
+/// Unterminated strings are reported as an unexpected end of file, as
+/// the text would be valid if the source continued.
+pub(crate) fn describe_invalid_token(text: &str) -> (ErrorCode, String) {
+    match text.chars().next() {
+        Some('"') => match unescape(text) {
+            Err(message) if message == UNTERMINATED_STRING => {
+                (ErrorCode::UnexpectedEndOfFile, message)
+            }
+            Err(message) => (ErrorCode::InvalidToken, message),
+            Ok(_) => (ErrorCode::InvalidToken, "invalid string literal".into()),
+        },
+        Some('0'..='9') => (
+            ErrorCode::InvalidToken,
+            format!("number literal `{}` is too large", text),
+        ),
+        _ => (ErrorCode::InvalidToken, format!("invalid token `{}`", text)),
+    }
+}
+
+/// The names of the special forms recognised by the parser. Any
+/// other form is a function call.
+pub const SPECIAL_FORMS: &[&str] = &[
+    "and",
+    "case",
+    "cond",
+    "define",
+    "define-syntax",
+    "defmacro",
+    "if",
+    "import",
+    "lambda",
+    "let",
+    "let*",
+    "letrec",
+    "letrec*",
+    "module",
+    "or",
+    "quote",
+    "set!",
+    "unless",
+    "when",
+];
+
+/// Syntax error codes
+///
+/// Each diagnostic produced by the parser is tagged with one of these
+/// codes so that tools can identify the kind of error without having
+/// to inspect the message.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum ErrorCode {
+    /// The source contains text which isn't a valid token
+    InvalidToken,
+    /// A token was found where it isn't allowed
+    UnexpectedToken,
+    /// The source ended before the expression was complete
+    UnexpectedEndOfFile,
+    /// A form didn't start with a symbol
+    InvalidForm,
+    /// A symbol was expected but something else was found
+    ExpectedSymbol,
+    /// A macro definition is malformed, or a use of a macro doesn't
+    /// match any of its rules
+    InvalidMacro,
+    /// A procedural macro raised an error, or returned something
+    /// which isn't code
+    MacroFailed,
+    /// A name was bound twice by the same parameter or binding list
+    DuplicateBinding,
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        let code = match self {
+            ErrorCode::InvalidToken => 1,
+            ErrorCode::UnexpectedToken => 2,
+            ErrorCode::UnexpectedEndOfFile => 3,
+            ErrorCode::InvalidForm => 4,
+            ErrorCode::ExpectedSymbol => 5,
+            ErrorCode::InvalidMacro => 6,
+            ErrorCode::MacroFailed => 7,
+            ErrorCode::DuplicateBinding => 8,
+        };
+        write!(out, "P{:04}", code)
+    }
+}
+
+/// Syntax error diagnostic
+///
+/// Describes a single problem found when parsing the source text.
+#[derive(Debug, PartialEq)]
+pub struct Diagnostic {
+    /// The kind of error
+    pub code: ErrorCode,
+    /// The location in the source text the error refers to
+    pub span: Span,
+    /// Human readable description of the error
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// Create a diagnostic with the given `code` at `span`
+    pub fn new(code: ErrorCode, span: Span, message: String) -> Self {
+        Diagnostic {
+            code,
+            span,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            out,
+            "error[{}]: {} at {}",
+            self.code, self.message, self.span
+        )
+    }
+}
+
+/// Parse source text into a structured AST program
+///
+/// This first reads the source text as a sequence of data, and then
+/// turns each datum into a top-level form. If the source contains
+/// syntax errors then every error found is returned instead.
+///
+/// Macros defined by the source are expanded as it is parsed.
+pub fn parse(source: &str) -> Result<ast::Program, Vec<Diagnostic>> {
+    parse_with_macros(source, &mut Macros::default())
+}
+
+/// Parse source text, expanding uses of the given `macros`. Any
+/// macros the source defines are added to them.
+pub(crate) fn parse_with_macros(
+    source: &str,
+    macros: &mut Macros,
+) -> Result<ast::Program, Vec<Diagnostic>> {
+    let (data, end, mut read_diagnostics) = Reader::new(source).read_all();
+    let mut syntax = Syntax::new(macros);
+    let forms = data
+        .into_iter()
+        .map(|datum| datum.and_then(|datum| syntax.expr(datum)))
+        .collect::<Vec<_>>();
+
+    // Both passes report in source order, so merge them. Where they
+    // both found a problem at the same place the syntax pass has more
+    // idea of what was expected, so its diagnostic is kept.
+    let mut diagnostics = syntax.into_diagnostics();
+    diagnostics.append(&mut read_diagnostics);
+    diagnostics.sort_by_key(|diagnostic| diagnostic.span.start());
+    diagnostics.dedup_by_key(|diagnostic| diagnostic.span);
+
+    match forms.into_iter().collect::<Option<Vec<_>>>() {
+        Some(forms) if diagnostics.is_empty() => Ok(ast::Program::new(forms, end)),
+        _ => Err(diagnostics),
+    }
+}
+
+/// Source text which has been read, ready to be parsed one top-level
+/// form at a time
+///
+/// A form can only be expanded once the forms before it have been
+/// evaluated, as they can define the macros it uses, and the functions
+/// those macros call.
+pub(crate) struct Forms {
+    data: std::vec::IntoIter<ast::Datum>,
+}
+
+impl Forms {
+    /// Read the source text. Only problems reading it are reported
+    /// here, as problems with each form can't be found until it is
+    /// parsed.
+    pub(crate) fn read(source: &str) -> Result<Self, Vec<Diagnostic>> {
+        let (data, _, diagnostics) = Reader::new(source).read_all();
+        match data.into_iter().collect::<Option<Vec<_>>>() {
+            Some(data) if diagnostics.is_empty() => Ok(Forms {
+                data: data.into_iter(),
+            }),
+            _ => Err(diagnostics),
+        }
+    }
+
+    /// Parse the next form, expanding uses of the given `macros`. Any
+    /// macros the form defines are added to them.
+    pub(crate) fn next(
+        &mut self,
+        macros: &mut Macros,
+    ) -> Option<Result<ast::Expr, Vec<Diagnostic>>> {
+        let datum = self.data.next()?;
+        let mut syntax = Syntax::new(macros);
+        let form = syntax.expr(datum);
+        let diagnostics = syntax.into_diagnostics();
+        Some(match form {
+            Some(form) if diagnostics.is_empty() => Ok(form),
+            _ => Err(diagnostics),
+        })
+    }
 }
 
 #[cfg(test)]
@@ -243,20 +516,30 @@ mod test {
 
     use super::*;
 
+    fn eof(at: u32) -> ast::Token {
+        ast::Token::with_span(ast::TokenKind::EndOfFile, Span::new(at, at))
+    }
+
     #[test]
     fn tokenise_number_literals() {
         assert_eq!(
-            vec![ast::Token::with_span(
-                ast::TokenKind::Number(0),
-                Span::new(ByteIndex(1), ByteIndex(2))
-            )],
+            vec![
+                ast::Token::with_span(
+                    ast::TokenKind::Number(0),
+                    Span::new(ByteIndex(1), ByteIndex(2))
+                ),
+                eof(2)
+            ],
             tokenise("0")
         );
         assert_eq!(
-            vec![ast::Token::with_span(
-                ast::TokenKind::Number(1234),
-                Span::new(ByteIndex(1), ByteIndex(5))
-            )],
+            vec![
+                ast::Token::with_span(
+                    ast::TokenKind::Number(1234),
+                    Span::new(ByteIndex(1), ByteIndex(5))
+                ),
+                eof(5)
+            ],
             tokenise("1234")
         );
     }
@@ -264,10 +547,13 @@ mod test {
     #[test]
     fn tokenise_symbols() {
         assert_eq!(
-            vec![ast::Token::with_span(
-                ast::TokenKind::Symbol("hello/world".into()),
-                Span::new(ByteIndex(1), ByteIndex(12))
-            )],
+            vec![
+                ast::Token::with_span(
+                    ast::TokenKind::Symbol("hello/world".into()),
+                    Span::new(ByteIndex(1), ByteIndex(12))
+                ),
+                eof(12)
+            ],
             tokenise("hello/world")
         );
         assert_eq!(
@@ -275,44 +561,75 @@ mod test {
                 ast::Token::with_span(
                     ast::TokenKind::Symbol("hello".into()),
                     Span::new(ByteIndex(1), ByteIndex(6))
-                ),
+                )
+                .with_trailing_trivia(vec![ast::Trivia::new(
+                    ast::TriviaKind::Whitespace,
+                    Span::new(ByteIndex(6), ByteIndex(7)),
+                    " "
+                )]),
                 ast::Token::with_span(
                     ast::TokenKind::Symbol("world".into()),
                     Span::new(ByteIndex(7), ByteIndex(12))
-                )
+                ),
+                eof(12)
             ],
             tokenise("hello world")
         );
         assert_eq!(
-            vec![ast::Token::with_span(
-                ast::TokenKind::Symbol("hello.world".into()),
-                Span::new(ByteIndex(1), ByteIndex(12))
-            )],
+            vec![
+                ast::Token::with_span(
+                    ast::TokenKind::Symbol("hello.world".into()),
+                    Span::new(ByteIndex(1), ByteIndex(12))
+                ),
+                eof(12)
+            ],
             tokenise("hello.world")
         );
         assert_eq!(
-            vec![ast::Token::with_span(
-                ast::TokenKind::Symbol("+".into()),
-                Span::new(ByteIndex(1), ByteIndex(2))
-            )],
+            vec![
+                ast::Token::with_span(
+                    ast::TokenKind::Symbol("+".into()),
+                    Span::new(ByteIndex(1), ByteIndex(2))
+                ),
+                eof(2)
+            ],
             tokenise("+")
-        )
+        );
+        // Every character the tokeniser accepts in a symbol, and no
+        // others, is a symbol character
+        for c in "_x9-".chars() {
+            assert!(is_symbol_char(c));
+            assert!(matches!(
+                tokenise(&format!("a{}", c))[0].kind,
+                ast::TokenKind::Symbol(_)
+            ));
+        }
+        for c in "é,\"".chars() {
+            assert!(!is_symbol_char(c));
+            assert_eq!(2, tokenise(&format!("a{}", c)).len() - 1);
+        }
     }
 
     #[test]
     fn tokenise_brackets() {
         assert_eq!(
-            vec![ast::Token::with_span(
-                ast::TokenKind::LeftBracket,
-                Span::new(ByteIndex(1), ByteIndex(2))
-            )],
+            vec![
+                ast::Token::with_span(
+                    ast::TokenKind::LeftBracket,
+                    Span::new(ByteIndex(1), ByteIndex(2))
+                ),
+                eof(2)
+            ],
             tokenise("(")
         );
         assert_eq!(
-            vec![ast::Token::with_span(
-                ast::TokenKind::RightBracket,
-                Span::new(ByteIndex(1), ByteIndex(2))
-            )],
+            vec![
+                ast::Token::with_span(
+                    ast::TokenKind::RightBracket,
+                    Span::new(ByteIndex(1), ByteIndex(2))
+                ),
+                eof(2)
+            ],
             tokenise(")")
         );
         assert_eq!(
@@ -324,7 +641,8 @@ mod test {
                 ast::Token::with_span(
                     ast::TokenKind::RightBracket,
                     Span::new(ByteIndex(2), ByteIndex(3))
-                )
+                ),
+                eof(3)
             ],
             tokenise("()")
         );
@@ -353,7 +671,8 @@ mod test {
                 ast::Token::with_span(
                     ast::TokenKind::RightBracket,
                     Span::new(ByteIndex(6), ByteIndex(7))
-                )
+                ),
+                eof(7)
             ],
             tokenise("((()))")
         );
@@ -361,44 +680,1015 @@ mod test {
 
     #[test]
     fn tokenise_comments() {
-        assert_eq!(Vec::<ast::Token>::new(), tokenise("; hello world"));
         assert_eq!(
-            Vec::<ast::Token>::new(),
+            vec![eof(14).with_leading_trivia(vec![ast::Trivia::new(
+                ast::TriviaKind::Comment,
+                Span::new(ByteIndex(1), ByteIndex(14)),
+                "; hello world"
+            )])],
+            tokenise("; hello world")
+        );
+        assert_eq!(
+            vec![eof(47).with_leading_trivia(vec![
+                ast::Trivia::new(
+                    ast::TriviaKind::Comment,
+                    Span::new(ByteIndex(1), ByteIndex(14)),
+                    "; hello world"
+                ),
+                ast::Trivia::new(
+                    ast::TriviaKind::Whitespace,
+                    Span::new(ByteIndex(14), ByteIndex(15)),
+                    "\n"
+                ),
+                ast::Trivia::new(
+                    ast::TriviaKind::Comment,
+                    Span::new(ByteIndex(15), ByteIndex(32)),
+                    "; another comment"
+                ),
+                ast::Trivia::new(
+                    ast::TriviaKind::Whitespace,
+                    Span::new(ByteIndex(32), ByteIndex(34)),
+                    "\r\n"
+                ),
+                ast::Trivia::new(
+                    ast::TriviaKind::Comment,
+                    Span::new(ByteIndex(34), ByteIndex(47)),
+                    "; windows eol"
+                ),
+            ])],
             tokenise("; hello world\n; another comment\r\n; windows eol")
         );
     }
 
+    #[test]
+    fn tokenise_trivia() {
+        assert_eq!(
+            vec![
+                ast::Token::with_span(
+                    ast::TokenKind::LeftBracket,
+                    Span::new(ByteIndex(3), ByteIndex(4))
+                )
+                .with_leading_trivia(vec![ast::Trivia::new(
+                    ast::TriviaKind::Whitespace,
+                    Span::new(ByteIndex(1), ByteIndex(3)),
+                    "\n "
+                )]),
+                ast::Token::with_span(
+                    ast::TokenKind::RightBracket,
+                    Span::new(ByteIndex(4), ByteIndex(5))
+                )
+                .with_trailing_trivia(vec![
+                    ast::Trivia::new(
+                        ast::TriviaKind::Whitespace,
+                        Span::new(ByteIndex(5), ByteIndex(6)),
+                        " "
+                    ),
+                    ast::Trivia::new(
+                        ast::TriviaKind::Comment,
+                        Span::new(ByteIndex(6), ByteIndex(11)),
+                        "; end"
+                    ),
+                    ast::Trivia::new(
+                        ast::TriviaKind::Whitespace,
+                        Span::new(ByteIndex(11), ByteIndex(12)),
+                        "\n"
+                    ),
+                ]),
+                ast::Token::with_span(
+                    ast::TokenKind::Number(1),
+                    Span::new(ByteIndex(15), ByteIndex(16))
+                )
+                .with_leading_trivia(vec![
+                    ast::Trivia::new(
+                        ast::TriviaKind::Comment,
+                        Span::new(ByteIndex(12), ByteIndex(14)),
+                        "; "
+                    ),
+                    ast::Trivia::new(
+                        ast::TriviaKind::Whitespace,
+                        Span::new(ByteIndex(14), ByteIndex(15)),
+                        "\n"
+                    ),
+                ])
+                .with_trailing_trivia(vec![ast::Trivia::new(
+                    ast::TriviaKind::Whitespace,
+                    Span::new(ByteIndex(16), ByteIndex(17)),
+                    "\n"
+                )]),
+                eof(18).with_leading_trivia(vec![ast::Trivia::new(
+                    ast::TriviaKind::Whitespace,
+                    Span::new(ByteIndex(17), ByteIndex(18)),
+                    "\n"
+                )])
+            ],
+            tokenise("\n () ; end\n; \n1\n\n")
+        );
+    }
+
     #[test]
     fn parse_atoms() {
         assert_eq!(
-            ast::Expr::Number(
+            Ok(ast::Program::new(
+                vec![ast::Expr::Number(
+                    ast::Token::with_span(
+                        ast::TokenKind::Number(64),
+                        Span::new(ByteIndex(1), ByteIndex(3))
+                    ),
+                    64
+                )],
+                eof(3)
+            )),
+            parse("64")
+        );
+        assert_eq!(
+            Ok(ast::Program::new(
+                vec![ast::Expr::Number(
+                    ast::Token::with_span(
+                        ast::TokenKind::Number(12364),
+                        Span::new(ByteIndex(1), ByteIndex(6))
+                    ),
+                    12364
+                )],
+                eof(6)
+            )),
+            parse("12364")
+        );
+        assert_eq!(
+            Ok(ast::Program::new(
+                vec![ast::Expr::Number(
+                    ast::Token::with_span(
+                        ast::TokenKind::Number(9223372036854775807),
+                        Span::new(ByteIndex(1), ByteIndex(20))
+                    ),
+                    9223372036854775807
+                )],
+                eof(20)
+            )),
+            parse("9223372036854775807")
+        );
+    }
+
+    #[test]
+    fn parse_conditionals() {
+        let space = |at: u32| {
+            vec![ast::Trivia::new(
+                ast::TriviaKind::Whitespace,
+                Span::new(ByteIndex(at), ByteIndex(at + 1)),
+                " ",
+            )]
+        };
+        let symbol = |name: &str, start: u32| {
+            ast::Token::with_span(
+                ast::TokenKind::Symbol(name.into()),
+                Span::new(ByteIndex(start), ByteIndex(start + name.len() as u32)),
+            )
+        };
+        let bracket = |kind: ast::TokenKind, at: u32| {
+            ast::Token::with_span(kind, Span::new(ByteIndex(at), ByteIndex(at + 1)))
+        };
+        assert_eq!(
+            Ok(ast::Program::new(
+                vec![ast::Expr::If(
+                    bracket(ast::TokenKind::LeftBracket, 1),
+                    symbol("if", 2).with_trailing_trivia(space(4)),
+                    Box::new(ast::Expr::Bool(
+                        ast::Token::with_span(
+                            ast::TokenKind::Bool(true),
+                            Span::new(ByteIndex(5), ByteIndex(7))
+                        )
+                        .with_trailing_trivia(space(7)),
+                        true
+                    )),
+                    Box::new(ast::Expr::Number(bracket(ast::TokenKind::Number(1), 8), 1)),
+                    None,
+                    bracket(ast::TokenKind::RightBracket, 9)
+                )],
+                eof(10)
+            )),
+            parse("(if #t 1)")
+        );
+        assert_eq!(
+            Ok(ast::Program::new(
+                vec![ast::Expr::Unless(
+                    bracket(ast::TokenKind::LeftBracket, 1),
+                    symbol("unless", 2).with_trailing_trivia(space(8)),
+                    Box::new(ast::Expr::Symbol(
+                        symbol("x", 9).with_trailing_trivia(space(10)),
+                        "x".into()
+                    )),
+                    vec![ast::Expr::Number(bracket(ast::TokenKind::Number(2), 11), 2)],
+                    bracket(ast::TokenKind::RightBracket, 12)
+                )],
+                eof(13)
+            )),
+            parse("(unless x 2)")
+        );
+        assert_eq!(
+            Ok(ast::Program::new(
+                vec![ast::Expr::Cond(
+                    bracket(ast::TokenKind::LeftBracket, 1),
+                    symbol("cond", 2).with_trailing_trivia(space(6)),
+                    vec![
+                        ast::CondClause::Arrow(
+                            bracket(ast::TokenKind::LeftBracket, 7),
+                            Box::new(ast::Expr::Symbol(
+                                symbol("x", 8).with_trailing_trivia(space(9)),
+                                "x".into()
+                            )),
+                            symbol("=>", 10).with_trailing_trivia(space(12)),
+                            Box::new(ast::Expr::Symbol(symbol("f", 13), "f".into())),
+                            bracket(ast::TokenKind::RightBracket, 14)
+                                .with_trailing_trivia(space(15))
+                        ),
+                        ast::CondClause::Else(
+                            bracket(ast::TokenKind::LeftBracket, 16),
+                            symbol("else", 17).with_trailing_trivia(space(21)),
+                            vec![ast::Expr::Number(bracket(ast::TokenKind::Number(0), 22), 0)],
+                            bracket(ast::TokenKind::RightBracket, 23)
+                        ),
+                    ],
+                    bracket(ast::TokenKind::RightBracket, 24)
+                )],
+                eof(25)
+            )),
+            parse("(cond (x => f) (else 0))")
+        );
+        let source = "(case (f) ((1 two) 'low) ((three) 3 4) (else 'high)) (when #t) (cond (x))";
+        let program = parse(source);
+        assert_eq!(
+            Err(vec![Diagnostic::new(
+                ErrorCode::UnexpectedToken,
+                Span::new(ByteIndex(62), ByteIndex(63)),
+                "expected expression, found `)`".into()
+            )]),
+            program
+        );
+        let source = "(case (f) ((1 two) 'low) ((three) 3 4) (else 'high)) (cond (x) (y 1 2))";
+        let program = parse(source).unwrap();
+        match program.forms() {
+            [ast::Expr::Case(_, _, key, clauses, _), ast::Expr::Cond(_, _, cond_clauses, _)] => {
+                assert!(matches!(**key, ast::Expr::Call(..)));
+                match &clauses[..] {
+                    [ast::CaseClause::Data(_, _, data, _, body, _), ast::CaseClause::Data(_, _, _, _, body2, _), ast::CaseClause::Else(..)] =>
+                    {
+                        assert_eq!(
+                            vec!["1", "two"],
+                            data.iter()
+                                .map(|d| d.to_string().trim_end().to_owned())
+                                .collect::<Vec<_>>()
+                        );
+                        assert_eq!(1, body.len());
+                        assert_eq!(2, body2.len());
+                    }
+                    other => panic!("expected case clauses, found {:?}", other),
+                }
+                match &cond_clauses[..] {
+                    [ast::CondClause::Test(_, _, empty, _), ast::CondClause::Test(_, _, body, close)] =>
+                    {
+                        assert!(empty.is_empty());
+                        assert_eq!(2, body.len());
+                        assert_eq!(Span::new(ByteIndex(70), ByteIndex(71)), close.span());
+                    }
+                    other => panic!("expected cond clauses, found {:?}", other),
+                }
+            }
+            other => panic!("expected case and cond, found {:?}", other),
+        }
+        assert_eq!(source, program.to_string());
+        assert_eq!(
+            Err(vec![
+                Diagnostic::new(
+                    ErrorCode::UnexpectedToken,
+                    Span::new(ByteIndex(16), ByteIndex(17)),
+                    "expected `)`, found `(`".into()
+                ),
+                Diagnostic::new(
+                    ErrorCode::UnexpectedToken,
+                    Span::new(ByteIndex(32), ByteIndex(33)),
+                    "expected `(`, found symbol `x`".into()
+                ),
+            ]),
+            parse("(cond (else 1) (#t 2)) (case 1 x)")
+        );
+    }
+
+    #[test]
+    fn tokenise_invalid() {
+        assert_eq!(
+            vec![
+                ast::Token::with_text(
+                    ast::TokenKind::Error,
+                    Span::new(ByteIndex(1), ByteIndex(2)),
+                    "#"
+                ),
+                eof(2)
+            ],
+            tokenise("#")
+        );
+        assert_eq!(
+            vec![
+                ast::Token::with_text(
+                    ast::TokenKind::Error,
+                    Span::new(ByteIndex(1), ByteIndex(21)),
+                    "92233720368547758070"
+                ),
+                eof(21)
+            ],
+            tokenise("92233720368547758070")
+        );
+    }
+
+    #[test]
+    fn parse_unexpected_tokens() {
+        assert_eq!(
+            Err(vec![Diagnostic::new(
+                ErrorCode::UnexpectedToken,
+                Span::new(ByteIndex(1), ByteIndex(2)),
+                "unexpected `)` with no matching `(`".into()
+            )]),
+            parse(")")
+        );
+        assert_eq!(
+            Err(vec![Diagnostic::new(
+                ErrorCode::UnexpectedEndOfFile,
+                Span::new(ByteIndex(2), ByteIndex(2)),
+                "expected function or special form, found end of file".into()
+            )]),
+            parse("(")
+        );
+        assert_eq!(
+            Err(vec![Diagnostic::new(
+                ErrorCode::InvalidToken,
+                Span::new(ByteIndex(1), ByteIndex(2)),
+                "invalid token `#`".into()
+            )]),
+            parse("#")
+        );
+    }
+
+    #[test]
+    fn parse_unclosed_forms() {
+        assert_eq!(
+            Err(vec![Diagnostic::new(
+                ErrorCode::UnexpectedEndOfFile,
+                Span::new(ByteIndex(9), ByteIndex(9)),
+                "expected `)`, found end of file".into()
+            )]),
+            parse("(foo 1 2")
+        );
+        assert_eq!(
+            Err(vec![Diagnostic::new(
+                ErrorCode::UnexpectedEndOfFile,
+                Span::new(ByteIndex(6), ByteIndex(6)),
+                "expected expression, found end of file".into()
+            )]),
+            parse("(if 1")
+        );
+    }
+
+    #[test]
+    fn parse_recovers_from_errors() {
+        assert_eq!(
+            Err(vec![
+                Diagnostic::new(
+                    ErrorCode::UnexpectedToken,
+                    Span::new(ByteIndex(11), ByteIndex(12)),
+                    "expected expression, found `)`".into()
+                ),
+                Diagnostic::new(
+                    ErrorCode::ExpectedSymbol,
+                    Span::new(ByteIndex(21), ByteIndex(22)),
+                    "expected symbol, found number `1`".into()
+                ),
+                Diagnostic::new(
+                    ErrorCode::InvalidForm,
+                    Span::new(ByteIndex(27), ByteIndex(28)),
+                    "expected function or special form, found `)`".into()
+                ),
+                Diagnostic::new(
+                    ErrorCode::UnexpectedToken,
+                    Span::new(ByteIndex(39), ByteIndex(40)),
+                    "expected `)`, found number `4`".into()
+                ),
+            ]),
+            parse("(begin (if) (define 1 2) () (if 1 2 3 4) (+ 1 2))")
+        );
+    }
+
+    #[test]
+    fn print_round_trips_source() {
+        let examples = concat!(env!("CARGO_MANIFEST_DIR"), "/examples");
+        for entry in std::fs::read_dir(examples).unwrap() {
+            let path = entry.unwrap().path();
+            let source = std::fs::read_to_string(&path).unwrap();
+            let expr = parse(&source).unwrap();
+            assert_eq!(source, expr.to_string(), "round trip of {:?}", path);
+        }
+        for source in &["64", " ( if  1 ; one\n  2 3 )\n\n", "(define x\n\t(+ 1 x))"] {
+            assert_eq!(*source, parse(source).unwrap().to_string());
+        }
+    }
+
+    #[test]
+    fn parse_multiple_forms() {
+        assert_eq!(
+            Ok(ast::Program::new(
+                vec![
+                    ast::Expr::Number(
+                        ast::Token::with_span(
+                            ast::TokenKind::Number(1),
+                            Span::new(ByteIndex(1), ByteIndex(2))
+                        )
+                        .with_trailing_trivia(vec![ast::Trivia::new(
+                            ast::TriviaKind::Whitespace,
+                            Span::new(ByteIndex(2), ByteIndex(3)),
+                            " "
+                        )]),
+                        1
+                    ),
+                    ast::Expr::Symbol(
+                        ast::Token::with_span(
+                            ast::TokenKind::Symbol("two".into()),
+                            Span::new(ByteIndex(3), ByteIndex(6))
+                        ),
+                        "two".into()
+                    ),
+                ],
+                eof(6)
+            )),
+            parse("1 two")
+        );
+        assert_eq!(Ok(ast::Program::new(Vec::new(), eof(1))), parse(""));
+    }
+
+    #[test]
+    fn parse_reports_trailing_junk() {
+        assert_eq!(
+            Err(vec![
+                Diagnostic::new(
+                    ErrorCode::UnexpectedToken,
+                    Span::new(ByteIndex(6), ByteIndex(7)),
+                    "unexpected `)` with no matching `(`".into()
+                ),
+                Diagnostic::new(
+                    ErrorCode::InvalidToken,
+                    Span::new(ByteIndex(8), ByteIndex(9)),
+                    "invalid token `#`".into()
+                ),
+            ]),
+            parse("(a 1)) #")
+        );
+    }
+
+    #[test]
+    fn tokenise_strings() {
+        assert_eq!(
+            vec![
                 ast::Token::with_span(
-                    ast::TokenKind::Number(64),
-                    Span::new(ByteIndex(1), ByteIndex(3))
+                    ast::TokenKind::String("hello world".into()),
+                    Span::new(ByteIndex(1), ByteIndex(14))
                 ),
-                64
-            ),
-            parse("64")
+                eof(14)
+            ],
+            tokenise("\"hello world\"")
         );
         assert_eq!(
-            ast::Expr::Number(
+            vec![
+                ast::Token::with_text(
+                    ast::TokenKind::String("a\n\t\"\\\u{1F3CE}".into()),
+                    Span::new(ByteIndex(1), ByteIndex(21)),
+                    "\"a\\n\\t\\\"\\\\\\u{1F3CE}\""
+                ),
+                eof(21)
+            ],
+            tokenise("\"a\\n\\t\\\"\\\\\\u{1F3CE}\"")
+        );
+        assert_eq!(
+            vec![
+                ast::Token::with_text(
+                    ast::TokenKind::Error,
+                    Span::new(ByteIndex(1), ByteIndex(6)),
+                    "\"open"
+                ),
+                eof(6)
+            ],
+            tokenise("\"open")
+        );
+    }
+
+    #[test]
+    fn parse_invalid_literals() {
+        assert_eq!(
+            Err(vec![Diagnostic::new(
+                ErrorCode::InvalidToken,
+                Span::new(ByteIndex(1), ByteIndex(5)),
+                "unknown escape sequence `\\q`".into()
+            )]),
+            parse("\"\\q\"")
+        );
+        assert_eq!(
+            Err(vec![Diagnostic::new(
+                ErrorCode::InvalidToken,
+                Span::new(ByteIndex(1), ByteIndex(13)),
+                "`\\u{110000}` is not a valid unicode character".into()
+            )]),
+            parse("\"\\u{110000}\"")
+        );
+        assert_eq!(
+            Err(vec![
+                Diagnostic::new(
+                    ErrorCode::UnexpectedEndOfFile,
+                    Span::new(ByteIndex(4), ByteIndex(10)),
+                    "unterminated string literal".into()
+                ),
+                Diagnostic::new(
+                    ErrorCode::UnexpectedEndOfFile,
+                    Span::new(ByteIndex(10), ByteIndex(10)),
+                    "expected `)`, found end of file".into()
+                )
+            ]),
+            parse("(a \"oops)")
+        );
+        assert_eq!(
+            Err(vec![Diagnostic::new(
+                ErrorCode::InvalidToken,
+                Span::new(ByteIndex(1), ByteIndex(21)),
+                "number literal `92233720368547758070` is too large".into()
+            )]),
+            parse("92233720368547758070")
+        );
+    }
+
+    #[test]
+    fn parse_lambda() {
+        let program = parse("(lambda (x y) (+ x y))").unwrap();
+        match &program.into_forms()[..] {
+            [ast::Expr::Lambda(_, _, _, params, _, body, _)] => {
+                let params: Vec<_> = params.iter().map(|p| p.text()).collect();
+                assert_eq!(vec!["x", "y"], params);
+                assert_eq!(1, body.len());
+            }
+            other => panic!("expected lambda, found {:?}", other),
+        }
+        assert_eq!(
+            Err(vec![Diagnostic::new(
+                ErrorCode::UnexpectedToken,
+                Span::new(ByteIndex(12), ByteIndex(13)),
+                "expected expression, found `)`".into()
+            )]),
+            parse("(lambda (x))")
+        );
+        assert_eq!(
+            Err(vec![
+                Diagnostic::new(
+                    ErrorCode::ExpectedSymbol,
+                    Span::new(ByteIndex(12), ByteIndex(13)),
+                    "expected symbol, found number `1`".into()
+                ),
+                Diagnostic::new(
+                    ErrorCode::UnexpectedToken,
+                    Span::new(ByteIndex(26), ByteIndex(27)),
+                    "expected `(`, found symbol `x`".into()
+                )
+            ]),
+            parse("(lambda (a 1) a) (lambda x x)")
+        );
+        assert_eq!(
+            Err(vec![Diagnostic::new(
+                ErrorCode::DuplicateBinding,
+                Span::new(ByteIndex(14), ByteIndex(15)),
+                "`a` is bound more than once".into()
+            )]),
+            parse("(lambda (a b a) a)")
+        );
+    }
+
+    #[test]
+    fn parse_let() {
+        let program = parse("(let ((x 1) (y 2)) x) (let* () 1) (letrec* ((f f)) f)").unwrap();
+        match &program.into_forms()[..] {
+            [ast::Expr::Let(_, _, bindings, body, _), ast::Expr::LetStar(_, _, empty, _, _), ast::Expr::Letrec(_, letrec_tok, _, _, _)] =>
+            {
+                let names: Vec<_> = bindings
+                    .bindings()
+                    .iter()
+                    .map(|b| b.name().text())
+                    .collect();
+                assert_eq!(vec!["x", "y"], names);
+                assert_eq!(
+                    Span::new(ByteIndex(13), ByteIndex(18)),
+                    bindings.bindings()[1].span()
+                );
+                assert_eq!(1, body.len());
+                assert!(empty.bindings().is_empty());
+                assert_eq!("letrec*", letrec_tok.text());
+            }
+            other => panic!("expected let forms, found {:?}", other),
+        }
+        let source = "(let loop ((i 0)) (loop i))";
+        let program = parse(source).unwrap();
+        match program.forms() {
+            [ast::Expr::NamedLet(_, _, name, bindings, body, _)] => {
+                assert_eq!("loop", name.text());
+                assert_eq!(1, bindings.bindings().len());
+                assert_eq!(1, body.len());
+            }
+            other => panic!("expected named let, found {:?}", other),
+        }
+        assert_eq!(source, program.to_string());
+        assert_eq!(
+            Err(vec![
+                Diagnostic::new(
+                    ErrorCode::UnexpectedToken,
+                    Span::new(ByteIndex(9), ByteIndex(10)),
+                    "expected expression, found `)`".into()
+                ),
+                Diagnostic::new(
+                    ErrorCode::ExpectedSymbol,
+                    Span::new(ByteIndex(25), ByteIndex(26)),
+                    "expected symbol, found number `1`".into()
+                ),
+                Diagnostic::new(
+                    ErrorCode::UnexpectedToken,
+                    Span::new(ByteIndex(40), ByteIndex(41)),
+                    "expected `(`, found `)`".into()
+                ),
+            ]),
+            parse("(let ((x)) x) (letrec ((1 2)) 1) (let x)")
+        );
+        // Only `let*` can bind the same name twice, as each of its
+        // bindings is in a new scope
+        let duplicate = |at: u32, name: &str| {
+            Diagnostic::new(
+                ErrorCode::DuplicateBinding,
+                Span::new(ByteIndex(at), ByteIndex(at + 1)),
+                format!("`{}` is bound more than once", name),
+            )
+        };
+        assert_eq!(
+            Err(vec![
+                duplicate(14, "x"),
+                duplicate(62, "f"),
+                duplicate(89, "i")
+            ]),
+            parse(
+                "(let ((x 1) (x 2)) x) (let* ((y 1) (y 2)) y) \
+                 (letrec ((f 1) (f 2)) f) (let loop ((i 0) (i 1)) i)"
+            )
+        );
+    }
+
+    #[test]
+    fn parse_call_with_expression_callee() {
+        let program = parse("((lambda (x) x) 1)").unwrap();
+        match &program.into_forms()[..] {
+            [ast::Expr::Call(_, callee, args, _)] => {
+                assert!(matches!(**callee, ast::Expr::Lambda(..)));
+                assert_eq!(1, args.len());
+            }
+            other => panic!("expected call, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn tokenise_booleans() {
+        assert_eq!(
+            vec![
                 ast::Token::with_span(
-                    ast::TokenKind::Number(12364),
-                    Span::new(ByteIndex(1), ByteIndex(6))
+                    ast::TokenKind::Bool(true),
+                    Span::new(ByteIndex(1), ByteIndex(3))
+                )
+                .with_trailing_trivia(vec![ast::Trivia::new(
+                    ast::TriviaKind::Whitespace,
+                    Span::new(ByteIndex(3), ByteIndex(4)),
+                    " "
+                )]),
+                ast::Token::with_span(
+                    ast::TokenKind::Bool(false),
+                    Span::new(ByteIndex(4), ByteIndex(6))
                 ),
-                12364
-            ),
-            parse("12364")
+                eof(6)
+            ],
+            tokenise("#t #f")
         );
         assert_eq!(
-            ast::Expr::Number(
+            Err(vec![Diagnostic::new(
+                ErrorCode::InvalidToken,
+                Span::new(ByteIndex(1), ByteIndex(6)),
+                "invalid token `#true`".into()
+            )]),
+            parse("#true")
+        );
+    }
+
+    #[test]
+    fn parse_and_or() {
+        let program = parse("(and #t 1) (or) (and (or #f x))").unwrap();
+        match &program.into_forms()[..] {
+            [ast::Expr::And(_, _, first, _), ast::Expr::Or(_, _, second, _), ast::Expr::And(_, _, third, _)] =>
+            {
+                assert!(matches!(
+                    first[..],
+                    [ast::Expr::Bool(_, true), ast::Expr::Number(_, 1)]
+                ));
+                assert!(second.is_empty());
+                assert!(matches!(third[..], [ast::Expr::Or(..)]));
+            }
+            other => panic!("expected and/or forms, found {:?}", other),
+        }
+        assert_eq!(
+            Err(vec![Diagnostic::new(
+                ErrorCode::UnexpectedEndOfFile,
+                Span::new(ByteIndex(7), ByteIndex(7)),
+                "expected `)`, found end of file".into()
+            )]),
+            parse("(and 1")
+        );
+    }
+
+    #[test]
+    fn tokenise_quote() {
+        assert_eq!(
+            vec![
+                ast::Token::with_span(ast::TokenKind::Quote, Span::new(ByteIndex(1), ByteIndex(2))),
                 ast::Token::with_span(
-                    ast::TokenKind::Number(9223372036854775807),
-                    Span::new(ByteIndex(1), ByteIndex(20))
+                    ast::TokenKind::Symbol("a".into()),
+                    Span::new(ByteIndex(2), ByteIndex(3))
                 ),
-                9223372036854775807
-            ),
-            parse("9223372036854775807")
+                eof(3)
+            ],
+            tokenise("'a")
+        );
+    }
+
+    #[test]
+    fn parse_quote() {
+        let program = parse("'(if) (quote (1 'x ()))").unwrap();
+        match &program.into_forms()[..] {
+            [ast::Expr::QuoteAbbrev(_, first), ast::Expr::Quote(_, _, second, _)] => {
+                match &**first {
+                    ast::Datum::List(_, items, _) => {
+                        assert!(matches!(items[..], [ast::Datum::Symbol(_, ref s)] if s == "if"))
+                    }
+                    other => panic!("expected list, found {:?}", other),
+                }
+                match &**second {
+                    ast::Datum::List(_, items, _) => assert!(matches!(
+                        items[..],
+                        [
+                            ast::Datum::Number(_, 1),
+                            ast::Datum::Quote(..),
+                            ast::Datum::List(_, ref empty, _)
+                        ] if empty.is_empty()
+                    )),
+                    other => panic!("expected list, found {:?}", other),
+                }
+            }
+            other => panic!("expected quotes, found {:?}", other),
+        }
+        assert_eq!(
+            Err(vec![
+                Diagnostic::new(
+                    ErrorCode::UnexpectedToken,
+                    Span::new(ByteIndex(8), ByteIndex(9)),
+                    "expected datum, found `)`".into()
+                ),
+                Diagnostic::new(
+                    ErrorCode::UnexpectedEndOfFile,
+                    Span::new(ByteIndex(15), ByteIndex(15)),
+                    "expected datum, found end of file".into()
+                )
+            ]),
+            parse("(quote ) '(1 '")
+        );
+    }
+
+    #[test]
+    fn parse_module_and_import() {
+        let source = "(module m (export a b) (define a 1)) (import m (prefix m:) (rename (m:a x)))";
+        let program = parse(source).unwrap();
+        assert_eq!(source, program.to_string());
+        match &program.into_forms()[..] {
+            [ast::Expr::Module(_, _, name, exports, body, _), ast::Expr::Import(_, _, module, options, _)] =>
+            {
+                assert_eq!("m", name.text());
+                assert_eq!(
+                    vec!["a", "b"],
+                    exports
+                        .symbols()
+                        .iter()
+                        .map(|t| t.text())
+                        .collect::<Vec<_>>()
+                );
+                assert!(matches!(body[..], [ast::Expr::Define(..)]));
+                assert_eq!("m", module.text());
+                match &options[..] {
+                    [ast::ImportOption::Prefix(_, _, prefix, _), ast::ImportOption::Rename(_, _, renames, _)] =>
+                    {
+                        assert_eq!("m:", prefix.text());
+                        assert!(matches!(renames[..], [(_, ref from, ref to, _)]
+                            if from.text() == "m:a" && to.text() == "x"));
+                    }
+                    other => panic!("expected import options, found {:?}", other),
+                }
+            }
+            other => panic!("expected module and import, found {:?}", other),
+        }
+        assert_eq!(
+            Err(vec![
+                Diagnostic::new(
+                    ErrorCode::UnexpectedToken,
+                    Span::new(ByteIndex(12), ByteIndex(18)),
+                    "expected `export`, found symbol `define`".into()
+                ),
+                Diagnostic::new(
+                    ErrorCode::InvalidForm,
+                    Span::new(ByteIndex(36), ByteIndex(40)),
+                    "expected `prefix` or `rename`, found symbol `only`".into()
+                ),
+            ]),
+            parse("(module m (define x 1)) (import m (only x))")
+        );
+    }
+
+    #[test]
+    fn parse_macros() {
+        let source =
+            "(define-syntax swap! (syntax-rules () ((_ a b) (list b a))))\n(swap! x (car y))";
+        let program = parse(source).unwrap();
+        match program.forms() {
+            [ast::Expr::DefineSyntax(_, _, name, _, _), ast::Expr::Expansion(span, macro_name, expansion)] =>
+            {
+                assert_eq!("swap!", name.text());
+                // The expansion keeps the location of the use
+                assert_eq!("swap!", macro_name);
+                assert_eq!(Span::new(ByteIndex(62), ByteIndex(79)), *span);
+                let (callee, args) = match &**expansion {
+                    ast::Expr::Call(_, callee, args, _) => (callee, args),
+                    other => panic!("expected a call, found {:?}", other),
+                };
+                // Free symbols in the template are renamed
+                assert!(matches!(**callee, ast::Expr::Symbol(_, ref s) if s == "list#1"));
+                // Expanded code keeps the spans of the text it came from
+                assert_eq!(Span::new(ByteIndex(49), ByteIndex(53)), callee.span());
+                assert_eq!(Span::new(ByteIndex(71), ByteIndex(78)), args[0].span());
+                assert_eq!(Span::new(ByteIndex(69), ByteIndex(70)), args[1].span());
+            }
+            other => panic!("expected macro definition and use, found {:?}", other),
+        }
+        assert_eq!(
+            "(define-syntax swap! (syntax-rules () ((_ a b) (list b a))))\n(list#1 (car y) x)",
+            program.to_string()
+        );
+
+        // Macro uses are read as data so can contain any datum
+        let program =
+            parse("(define-syntax q (syntax-rules () ((_ x ...) '(x ...)))) (q () (if) 1)")
+                .unwrap();
+        assert_eq!("'(() (if) 1)", program.forms()[1].to_string().trim_start());
+
+        assert_eq!(
+            Err(vec![Diagnostic::new(
+                ErrorCode::InvalidMacro,
+                Span::new(ByteIndex(47), ByteIndex(54)),
+                "no rule of the macro `m` matches this use".into()
+            )]),
+            parse("(define-syntax m (syntax-rules () ((_ a) a))) (m 1 2)")
+        );
+        assert_eq!(
+            Err(vec![Diagnostic::new(
+                ErrorCode::InvalidMacro,
+                Span::new(ByteIndex(18), ByteIndex(21)),
+                "expected `(syntax-rules (<literal>...) <rule>...)`".into()
+            )]),
+            parse("(define-syntax m (x))")
+        );
+        assert_eq!(
+            Err(vec![Diagnostic::new(
+                ErrorCode::InvalidMacro,
+                Span::new(ByteIndex(47), ByteIndex(50)),
+                "an ellipsis must follow a pattern, and can only be used once in each list".into()
+            )]),
+            parse("(define-syntax m (syntax-rules () ((_ a ... b ...) a)))")
+        );
+        assert_eq!(
+            Err(vec![Diagnostic::new(
+                ErrorCode::InvalidMacro,
+                Span::new(ByteIndex(16), ByteIndex(18)),
+                "the special form `if` can't be redefined".into()
+            )]),
+            parse("(define-syntax if (syntax-rules () ((_) 1)))")
+        );
+        assert!(matches!(
+            &parse("(define-syntax f (syntax-rules () ((_ x) (f x)))) (f 1)").unwrap_err()[..],
+            [Diagnostic { code: ErrorCode::InvalidMacro, ref message, .. }]
+                if message == "macro expansion is nested too deeply, it may never finish"
+        ));
+    }
+
+    #[test]
+    fn parse_defmacro() {
+        let source = "(defmacro twice (e) (list 'begin e e))\n(twice (f 1))";
+        let program = parse(source).unwrap();
+        match program.forms() {
+            [ast::Expr::DefMacro(_, _, name, _, params, _, body, _), ast::Expr::Expansion(_, _, expansion)] =>
+            {
+                let (callee, args) = match &**expansion {
+                    ast::Expr::Call(_, callee, args, _) => (callee, args),
+                    other => panic!("expected a call, found {:?}", other),
+                };
+                assert_eq!("twice", name.text());
+                assert_eq!(
+                    vec!["e"],
+                    params.iter().map(|p| p.text()).collect::<Vec<_>>()
+                );
+                assert_eq!(1, body.len());
+                assert!(matches!(**callee, ast::Expr::Symbol(_, ref s) if s == "begin"));
+                assert_eq!(2, args.len());
+                // Code built by the macro refers to the whole use
+                let span = Span::new(ByteIndex(40), ByteIndex(53));
+                assert_eq!(span, callee.span());
+                assert_eq!(span, args[1].span());
+            }
+            other => panic!("expected macro definition and use, found {:?}", other),
+        }
+        assert_eq!(
+            "(defmacro twice (e) (list 'begin e e))\n(begin (f 1) (f 1))",
+            program.to_string()
+        );
+
+        assert_eq!(
+            Err(vec![Diagnostic::new(
+                ErrorCode::MacroFailed,
+                Span::new(ByteIndex(28), ByteIndex(35)),
+                "the macro `bad` failed: expected pair, found number `1`".into()
+            )]),
+            parse("(defmacro bad (x) (car x)) (bad 1)")
+        );
+        assert_eq!(
+            Err(vec![Diagnostic::new(
+                ErrorCode::MacroFailed,
+                Span::new(ByteIndex(29), ByteIndex(34)),
+                "the macro `m` returned pair `(1 . 2)`, which isn't code".into()
+            )]),
+            parse("(defmacro m (x) (cons 1 x)) (m 2)")
+        );
+        assert_eq!(
+            Err(vec![Diagnostic::new(
+                ErrorCode::InvalidMacro,
+                Span::new(ByteIndex(11), ByteIndex(16)),
+                "the special form `quote` can't be redefined".into()
+            )]),
+            parse("(defmacro quote (x) x)")
+        );
+    }
+
+    #[test]
+    fn special_forms_are_not_calls() {
+        for form in SPECIAL_FORMS {
+            // Forms with the wrong shape are errors, which is fine
+            if let Ok(program) = parse(&format!("({} x)", form)) {
+                assert!(
+                    !matches!(program.forms(), [ast::Expr::Call(..)]),
+                    "`{}` parsed as a call",
+                    form
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn tokenise_shebang() {
+        assert_eq!(
+            vec![
+                ast::Token::with_span(
+                    ast::TokenKind::Number(1),
+                    Span::new(ByteIndex(20), ByteIndex(21))
+                )
+                .with_leading_trivia(vec![
+                    ast::Trivia::new(
+                        ast::TriviaKind::Comment,
+                        Span::new(ByteIndex(1), ByteIndex(19)),
+                        "#!/usr/bin/env f1 "
+                    ),
+                    ast::Trivia::new(
+                        ast::TriviaKind::Whitespace,
+                        Span::new(ByteIndex(19), ByteIndex(20)),
+                        "\n"
+                    )
+                ]),
+                eof(21)
+            ],
+            tokenise("#!/usr/bin/env f1 \n1")
+        );
+        // Only the first line can be a shebang
+        assert_eq!(
+            Err(vec![Diagnostic::new(
+                ErrorCode::InvalidToken,
+                Span::new(ByteIndex(3), ByteIndex(5)),
+                "invalid token `#!`".into()
+            )]),
+            parse("1\n#!")
         );
     }
 }
@@ -0,0 +1,226 @@
+//! Datum Reader
+//!
+//! The reader groups the tokens of the source text into data: atoms,
+//! quoted data and bracketed lists of other data. It knows nothing
+//! about special forms, so the same data can be turned into code by the
+//! syntax pass, quoted, passed to macros or loaded from data files.
+
+use super::ast::{self, Datum, Token, TokenKind};
+use super::parse::{self, Diagnostic, ErrorCode};
+use codespan::Span;
+
+/// Reader state
+///
+/// Contains the lookahead information for the reader, along with the
+/// diagnostics produced so far.
+pub(crate) struct Reader {
+    tokens: std::iter::Peekable<std::vec::IntoIter<Token>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Reader {
+    /// Create a reader for the given source text
+    pub(crate) fn new(source: &str) -> Self {
+        Reader {
+            tokens: parse::tokenise(source).into_iter().peekable(),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    /// Record a diagnostic. A single problem can cause several data to
+    /// fail at the same token, so only the first diagnostic at any
+    /// given location is kept.
+    fn error(&mut self, code: ErrorCode, span: Span, message: String) {
+        if self.diagnostics.last().map(|d| d.span) != Some(span) {
+            self.diagnostics.push(Diagnostic::new(code, span, message));
+        }
+    }
+
+    /// Record an error for the token we are looking at
+    fn error_at_peek(&mut self, code: ErrorCode, expected: &str) {
+        if let Some(token) = self.tokens.peek() {
+            let code = match token.kind {
+                TokenKind::EndOfFile => ErrorCode::UnexpectedEndOfFile,
+                _ => code,
+            };
+            let span = token.span();
+            let message = format!("expected {}, found {}", expected, token.kind);
+            self.error(code, span, message);
+        }
+    }
+
+    /// Get the kind of the token we are looking at
+    fn peek_kind(&mut self) -> &TokenKind {
+        match self.tokens.peek() {
+            Some(token) => &token.kind,
+            None => &TokenKind::EndOfFile,
+        }
+    }
+
+    /// Read every top-level datum in the source
+    ///
+    /// Returns the data, the end of file token and the diagnostics
+    /// produced. Data containing invalid tokens are reported and left
+    /// as `None`, so that the rest of the source can still be
+    /// checked. Brackets with no matching `(` are reported and
+    /// skipped.
+    pub(crate) fn read_all(mut self) -> (Vec<Option<Datum>>, Token, Vec<Diagnostic>) {
+        let mut data = Vec::new();
+        loop {
+            match self.peek_kind() {
+                TokenKind::EndOfFile => break,
+                TokenKind::RightBracket => {
+                    let token = self.tokens.next().unwrap();
+                    self.error(
+                        ErrorCode::UnexpectedToken,
+                        token.span(),
+                        "unexpected `)` with no matching `(`".into(),
+                    );
+                }
+                _ => data.push(self.read_datum()),
+            }
+        }
+        let end = self
+            .tokens
+            .next()
+            .expect("the tokeniser always produces an end of file token");
+        (data, end, self.diagnostics)
+    }
+
+    /// Read a single datum
+    ///
+    /// If no datum starts at the current token then a diagnostic is
+    /// recorded and `None` is returned. A closing bracket is never
+    /// consumed here so that the enclosing list can recover.
+    fn read_datum(&mut self) -> Option<Datum> {
+        use TokenKind::*;
+        match self.peek_kind() {
+            RightBracket | EndOfFile => {
+                self.error_at_peek(ErrorCode::UnexpectedToken, "datum");
+                None
+            }
+            _ => {
+                let token = self.tokens.next().unwrap();
+                match token.kind {
+                    LeftBracket => self.read_list(token),
+                    Quote => {
+                        let datum = self.read_datum();
+                        Some(Datum::Quote(token, Box::new(datum?)))
+                    }
+                    Number(n) => Some(Datum::Number(token, n)),
+                    Bool(b) => Some(Datum::Bool(token, b)),
+                    String(ref s) => {
+                        let s = s.clone();
+                        Some(Datum::String(token, s))
+                    }
+                    Symbol(ref s) => {
+                        let sym = s.clone();
+                        Some(Datum::Symbol(token, sym))
+                    }
+                    Error => {
+                        let (code, message) = parse::describe_invalid_token(token.text());
+                        self.error(code, token.span(), message);
+                        None
+                    }
+                    RightBracket | EndOfFile => unreachable!(),
+                }
+            }
+        }
+    }
+
+    /// Read the items of a list, given its opening bracket
+    ///
+    /// A list which is still open at the end of the source is reported,
+    /// but if its items are valid it is returned closed by the end of
+    /// file token. That lets the syntax pass explain what the form was
+    /// missing.
+    fn read_list(&mut self, open: Token) -> Option<Datum> {
+        let mut items = Vec::new();
+        loop {
+            match self.peek_kind() {
+                TokenKind::RightBracket | TokenKind::EndOfFile => break,
+                _ => items.push(self.read_datum()),
+            }
+        }
+        let close = match self.peek_kind() {
+            TokenKind::RightBracket => self.tokens.next().unwrap(),
+            _ => {
+                self.error_at_peek(ErrorCode::UnexpectedToken, "`)`");
+                self.tokens.peek().unwrap().clone()
+            }
+        };
+        let items = items.into_iter().collect::<Option<Vec<_>>>();
+        Some(Datum::List(open, items?, close))
+    }
+}
+
+/// Read source text as a sequence of data
+///
+/// Unlike `parse` the data aren't interpreted as code, so special form
+/// names are just symbols and lists can be empty. If the source
+/// contains syntax errors then every error found is returned instead.
+pub fn read(source: &str) -> Result<Vec<ast::Datum>, Vec<Diagnostic>> {
+    let (data, _, diagnostics) = Reader::new(source).read_all();
+    match data.into_iter().collect::<Option<Vec<_>>>() {
+        Some(data) if diagnostics.is_empty() => Ok(data),
+        _ => Err(diagnostics),
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use codespan::ByteIndex;
+
+    #[test]
+    fn read_data() {
+        let data = read("(if) 'x (1 (\"two\" #t)) ()").unwrap();
+        assert_eq!(4, data.len());
+        assert_eq!(
+            vec!["(if)", "'x", "(1 (\"two\" #t))", "()"],
+            data.iter()
+                .map(|datum| datum.to_string().trim_end().to_owned())
+                .collect::<Vec<_>>()
+        );
+        match &data[2] {
+            Datum::List(_, items, _) => {
+                assert_eq!(Span::new(ByteIndex(12), ByteIndex(22)), items[1].span())
+            }
+            other => panic!("expected a list, found {:?}", other),
+        }
+        assert_eq!(Ok(Vec::new()), read(" ; nothing here\n"));
+    }
+
+    #[test]
+    fn read_reports_errors() {
+        assert_eq!(
+            Err(vec![
+                Diagnostic::new(
+                    ErrorCode::UnexpectedToken,
+                    Span::new(ByteIndex(4), ByteIndex(5)),
+                    "unexpected `)` with no matching `(`".into()
+                ),
+                Diagnostic::new(
+                    ErrorCode::InvalidToken,
+                    Span::new(ByteIndex(7), ByteIndex(8)),
+                    "invalid token `#`".into()
+                ),
+                Diagnostic::new(
+                    ErrorCode::UnexpectedEndOfFile,
+                    Span::new(ByteIndex(13), ByteIndex(13)),
+                    "expected datum, found end of file".into()
+                ),
+            ]),
+            read("(a)) (# (b '")
+        );
+        assert_eq!(
+            Err(vec![Diagnostic::new(
+                ErrorCode::UnexpectedEndOfFile,
+                Span::new(ByteIndex(7), ByteIndex(7)),
+                "expected `)`, found end of file".into()
+            )]),
+            read("(a (b)")
+        );
+    }
+}
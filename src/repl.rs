@@ -0,0 +1,348 @@
+//! Interactive REPL
+//!
+//! When standard input is a terminal lines are read with a line editor,
+//! which provides history, tab completion of bound symbols and
+//! highlighting of matching brackets. Otherwise input is read line by
+//! line without any editing.
+
+use formula_one::{
+    is_symbol_char, parse, tokenise, Error, ErrorCode, Interpreter, Value, SPECIAL_FORMS,
+};
+
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::{CmdKind, Highlighter, MatchingBracketHighlighter};
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+
+use std::borrow::Cow;
+use std::fs;
+use std::io::{prelude::*, IsTerminal};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::time::Instant;
+
+/// Prompt shown at the start of each new input
+const PROMPT: &str = "\u{1F3CE}  > ";
+
+/// Prompt shown when more lines are needed to complete the input
+const CONTINUATION_PROMPT: &str = "   .. ";
+
+/// Name of the history file in the user's home directory
+const HISTORY_FILE: &str = ".formula-one-history";
+
+/// Help text for the REPL commands
+const HELP: &str = "\
+ :env           list the bindings in the global environment
+ :load <file>   evaluate a file in the global environment
+ :reset         start again with a fresh global environment
+ :ast <expr>    show the syntax tree for an expression
+ :tokens <expr> show the tokens for an expression
+ :time <expr>   evaluate an expression and show how long it took
+ :help          show this help
+ :quit          leave the REPL";
+
+/// Read, evaluate and print input until the end of standard input
+/// or a `:quit` command. Interpreters for the session are created with
+/// `new_interpreter`.
+///
+/// Definitions can be re-entered at the REPL, and files loaded again,
+/// to replace the global bindings they made before.
+pub fn run(new_interpreter: impl Fn() -> Interpreter) {
+    let new_interpreter = || {
+        let mut interpreter = new_interpreter();
+        interpreter.allow_redefinition(true);
+        interpreter
+    };
+    let mut interpreter = new_interpreter();
+    let mut input = Input::new();
+    loop {
+        input.set_completions(&interpreter);
+        let source = match input.read() {
+            Some(source) => source,
+            None => break,
+        };
+        let trimmed = source.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        if let Some(command) = trimmed.strip_prefix(':') {
+            if !run_command(command, &mut interpreter, &new_interpreter) {
+                break;
+            }
+            continue;
+        }
+        eval_and_print("<repl>", &source, || interpreter.eval_str(&source));
+    }
+}
+
+/// Evaluate `source` with `eval` and print the result
+fn eval_and_print(name: &str, source: &str, eval: impl FnOnce() -> Result<Value, Error>) {
+    // Builtins report errors rather than panicking, but a bug in
+    // one shouldn't take the whole session down with it.
+    let result = panic::catch_unwind(AssertUnwindSafe(eval));
+    match result {
+        Ok(result) => crate::print(name, source, result),
+        Err(_) => println!(" !! internal error evaluating input"),
+    }
+}
+
+/// Run a REPL command, without its leading `:`. Returns `false` if
+/// the REPL should exit.
+fn run_command(
+    command: &str,
+    interpreter: &mut Interpreter,
+    new_interpreter: &dyn Fn() -> Interpreter,
+) -> bool {
+    let (name, arg) = match command.split_once(char::is_whitespace) {
+        Some((name, arg)) => (name, arg.trim()),
+        None => (command, ""),
+    };
+    match name {
+        "env" => {
+            for (name, value) in interpreter.globals() {
+                println!(" {} = {}", name, value);
+            }
+        }
+        "load" if !arg.is_empty() => match fs::read_to_string(arg) {
+            Ok(source) => eval_and_print(arg, &source, || interpreter.eval_file(arg, &source)),
+            Err(error) => println!(" !! could not read `{}`: {}", arg, error),
+        },
+        "reset" => *interpreter = new_interpreter(),
+        "ast" => match interpreter.parse(arg) {
+            Ok(program) => {
+                for form in program.forms() {
+                    println!("{:#?}", form);
+                }
+            }
+            Err(error) => print!("{}", error.render("<repl>", arg)),
+        },
+        "tokens" => {
+            for token in tokenise(arg) {
+                println!(" {} {}", token.span(), token.kind);
+            }
+        }
+        "time" => {
+            let start = Instant::now();
+            eval_and_print("<repl>", arg, || interpreter.eval_str(arg));
+            println!(" took {:.2?}", start.elapsed());
+        }
+        "help" => println!("{}", HELP),
+        "quit" | "q" => return false,
+        "load" => println!(" !! usage: :load <file>"),
+        _ => println!(" !! unknown command `:{}`, try `:help`", name),
+    }
+    true
+}
+
+/// Check if `source` is ready to be evaluated
+///
+/// Source is complete unless parsing it runs off the end of the
+/// text, such as when brackets are left open or a string literal is
+/// unterminated. Other syntax errors are left for evaluation to
+/// report.
+fn is_complete(source: &str) -> bool {
+    match parse(source) {
+        Ok(_) => true,
+        Err(diagnostics) => !diagnostics
+            .iter()
+            .any(|d| d.code == ErrorCode::UnexpectedEndOfFile),
+    }
+}
+
+/// The source of REPL input
+enum Input {
+    /// Lines are read with a line editor. History is saved to the
+    /// given path, if there is one.
+    Editor(Box<Editor<ReplHelper, DefaultHistory>>, Option<PathBuf>),
+    /// Lines are read directly from standard input
+    Plain,
+}
+
+/// The result of reading a single line of input
+enum Line {
+    /// A line of text, including its line ending
+    Text(String),
+    /// The user cancelled the current input
+    Interrupted,
+    /// Standard input was closed, or couldn't be read
+    Eof,
+}
+
+impl Input {
+    /// Create the input for the REPL, using a line editor if
+    /// standard input is a terminal
+    fn new() -> Self {
+        if !std::io::stdin().is_terminal() {
+            return Input::Plain;
+        }
+        let mut editor = match Editor::new() {
+            Ok(editor) => editor,
+            Err(_) => return Input::Plain,
+        };
+        editor.set_helper(Some(ReplHelper::default()));
+        let history = std::env::home_dir().map(|home| home.join(HISTORY_FILE));
+        if let Some(ref path) = history {
+            // There is no history the first time the REPL is run
+            let _ = editor.load_history(path);
+        }
+        Input::Editor(Box::new(editor), history)
+    }
+
+    /// Update the symbols offered by tab completion to the ones
+    /// bound in the interpreter's global environment
+    fn set_completions(&mut self, interpreter: &Interpreter) {
+        if let Input::Editor(editor, _) = self {
+            if let Some(helper) = editor.helper_mut() {
+                helper.symbols = interpreter
+                    .globals()
+                    .into_iter()
+                    .map(|(name, _)| name)
+                    .chain(interpreter.macros())
+                    .chain(SPECIAL_FORMS.iter().map(|&form| form.into()))
+                    .collect();
+                helper.symbols.sort();
+            }
+        }
+    }
+
+    /// Read the input string from source
+    ///
+    /// Lines are read until the input forms a complete program,
+    /// showing the continuation prompt for each line after the
+    /// first. Returns `None` once standard input is closed.
+    fn read(&mut self) -> Option<String> {
+        let mut buff = String::new();
+        let mut prompt = PROMPT;
+        loop {
+            match self.read_line(prompt) {
+                Line::Text(line) => {
+                    buff.push_str(&line);
+                    if is_complete(&buff) {
+                        break;
+                    }
+                    prompt = CONTINUATION_PROMPT;
+                }
+                Line::Interrupted => {
+                    buff.clear();
+                    prompt = PROMPT;
+                }
+                // Any incomplete input is still evaluated so that its
+                // errors are reported.
+                Line::Eof if buff.trim().is_empty() => return None,
+                Line::Eof => break,
+            }
+        }
+        if let Input::Editor(editor, _) = self {
+            let _ = editor.add_history_entry(buff.trim_end());
+        }
+        self.save_history();
+        Some(buff)
+    }
+
+    /// Read a single line of input after showing `prompt`
+    fn read_line(&mut self, prompt: &str) -> Line {
+        match self {
+            Input::Editor(editor, _) => match editor.readline(prompt) {
+                Ok(line) => Line::Text(line + "\n"),
+                Err(ReadlineError::Interrupted) => Line::Interrupted,
+                Err(ReadlineError::Eof) => Line::Eof,
+                Err(error) => {
+                    println!(" !! {}", error);
+                    Line::Eof
+                }
+            },
+            Input::Plain => {
+                print!("{}", prompt);
+                let _ = std::io::stdout().flush();
+                let mut line = String::new();
+                match std::io::stdin().read_line(&mut line) {
+                    Ok(0) => {
+                        println!();
+                        Line::Eof
+                    }
+                    Ok(_) => Line::Text(line),
+                    Err(error) if error.kind() == std::io::ErrorKind::InvalidData => {
+                        println!(" !! {}", error);
+                        Line::Interrupted
+                    }
+                    Err(error) => {
+                        println!(" !! {}", error);
+                        Line::Eof
+                    }
+                }
+            }
+        }
+    }
+
+    /// Add any new entries in the editor's history to the history
+    /// file. This is done after each entry is read, so that history
+    /// isn't lost if the session ends without returning to the REPL,
+    /// such as when a program calls `exit`.
+    fn save_history(&mut self) {
+        if let Input::Editor(editor, Some(path)) = self {
+            if let Err(error) = editor.append_history(path) {
+                println!(" !! could not save history: {}", error);
+            }
+        }
+    }
+}
+
+/// Line editor helper
+///
+/// Completes symbols from the global environment and highlights the
+/// bracket matching the one at the cursor.
+#[derive(Default)]
+struct ReplHelper {
+    symbols: Vec<String>,
+    brackets: MatchingBracketHighlighter,
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let start = line[..pos]
+            .char_indices()
+            .rev()
+            .take_while(|&(_, c)| is_symbol_char(c))
+            .last()
+            .map_or(pos, |(idx, _)| idx);
+        let prefix = &line[start..pos];
+        let candidates = self
+            .symbols
+            .iter()
+            .filter(|symbol| symbol.starts_with(prefix))
+            .map(|symbol| Pair {
+                display: symbol.clone(),
+                replacement: symbol.clone(),
+            })
+            .collect();
+        Ok((start, candidates))
+    }
+}
+
+impl Highlighter for ReplHelper {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        self.brackets.highlight(line, pos)
+    }
+
+    fn highlight_char(&self, line: &str, pos: usize, kind: CmdKind) -> bool {
+        self.brackets.highlight_char(line, pos, kind)
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
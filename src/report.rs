@@ -0,0 +1,76 @@
+//! Error Reporting
+//!
+//! This module renders parse diagnostics and evaluation errors as
+//! source snippets using `codespan_reporting`. Spans in the syntax tree
+//! are offset by one from the byte position in the source, so each is
+//! converted back to a byte range before being labelled.
+
+use super::eval::EvalError;
+use super::parse::Diagnostic;
+
+use codespan::Span;
+use codespan_reporting::diagnostic::{self, Label};
+use codespan_reporting::files::SimpleFile;
+use codespan_reporting::term::{self, termcolor::NoColor};
+
+use std::ops::Range;
+
+/// A diagnostic ready to be rendered against a single source file
+type Report = diagnostic::Diagnostic<()>;
+
+/// The most calls from an error's trace which are labelled
+const MAX_TRACE: usize = 10;
+
+/// Convert a span in the syntax tree into a byte range in `source`
+fn to_range(span: Span, source: &str) -> Range<usize> {
+    let clamp = |index: usize| index.saturating_sub(1).min(source.len());
+    clamp(span.start().to_usize())..clamp(span.end().to_usize())
+}
+
+/// Build the report for a syntax error
+pub fn parse_report(diagnostic: &Diagnostic, source: &str) -> Report {
+    Report::error()
+        .with_code(diagnostic.code.to_string())
+        .with_message(diagnostic.message.clone())
+        .with_labels(vec![Label::primary((), to_range(diagnostic.span, source))])
+}
+
+/// Build the report for an evaluation error
+///
+/// The primary label points at the expression which raised the error
+/// and the innermost calls in the trace get secondary labels. Deep
+/// traces only have the number of calls left out noted.
+pub fn eval_report(error: &EvalError, source: &str) -> Report {
+    let mut labels = Vec::new();
+    if let Some(span) = error.span() {
+        labels.push(Label::primary((), to_range(span, source)));
+    }
+    for frame in error.trace().iter().take(MAX_TRACE) {
+        labels.push(
+            Label::secondary((), to_range(frame.span, source)).with_message(frame.to_string()),
+        );
+    }
+    let mut notes = Vec::new();
+    if error.trace().len() > MAX_TRACE {
+        let omitted = error.trace().len() - MAX_TRACE;
+        notes.push(format!("... and {} more calls", omitted));
+    }
+    Report::error()
+        .with_code(error.kind().code())
+        .with_message(error.kind().to_string())
+        .with_labels(labels)
+        .with_notes(notes)
+}
+
+/// Render `reports` against the source file `name`
+pub fn render(name: &str, source: &str, reports: &[Report]) -> String {
+    let file = SimpleFile::new(name, source);
+    let config = term::Config::default();
+    let mut out = NoColor::new(Vec::new());
+    for report in reports {
+        // Writing into a `Vec` can't fail, and the only other errors
+        // come from labels outside the file which `to_range` prevents.
+        term::emit(&mut out, &config, &file, report).expect("rendering diagnostic");
+    }
+    String::from_utf8_lossy(&out.into_inner()).into_owned()
+}
@@ -0,0 +1,737 @@
+//! Syntax Pass
+//!
+//! The syntax pass turns the data produced by the reader into
+//! expressions. Each list is checked against the shape of the special
+//! form it names, and uses of macros are expanded with the expansion
+//! turned into an expression in their place.
+
+use super::ast::{self, Datum, Expr, Token, TokenKind};
+use super::expand::{self, Macro, Macros, SyntaxRules};
+use super::parse::{Diagnostic, ErrorCode, SPECIAL_FORMS};
+use codespan::Span;
+use std::rc::Rc;
+
+/// The most tokens macro expansion can produce while parsing a single
+/// source text. This stops macros which expand forever.
+const EXPANSION_LIMIT: usize = 1_000_000;
+
+/// The deepest that macro uses can be nested within the expansions of
+/// other macros. Each level is converted recursively, so this stops
+/// macros which expand into themselves from overflowing the stack.
+const EXPANSION_DEPTH_LIMIT: usize = 64;
+
+/// The items of a list which haven't been converted yet, along with
+/// the bracket which closes it
+///
+/// Lists which the reader found still open at the end of the source
+/// are closed by the end of file token instead.
+struct Items {
+    items: std::iter::Peekable<std::vec::IntoIter<Datum>>,
+    close: Token,
+}
+
+impl Items {
+    /// Create a list of items to convert
+    fn new(items: Vec<Datum>, close: Token) -> Self {
+        Items {
+            items: items.into_iter().peekable(),
+            close,
+        }
+    }
+
+    /// Look at the next item without consuming it
+    fn peek(&mut self) -> Option<&Datum> {
+        self.items.peek()
+    }
+
+    /// Consume the next item
+    fn next(&mut self) -> Option<Datum> {
+        self.items.next()
+    }
+
+    /// Get the token at the start of the next item, or the closing
+    /// bracket if there are no items left
+    fn peek_token(&mut self) -> &Token {
+        match self.items.peek() {
+            Some(datum) => first_token(datum),
+            None => &self.close,
+        }
+    }
+
+    /// Check if every item has been consumed
+    fn at_close(&mut self) -> bool {
+        self.items.peek().is_none()
+    }
+
+    /// Consume the next item if it is a symbol
+    fn next_symbol(&mut self) -> Option<Token> {
+        match self.items.next_if(|item| matches!(item, Datum::Symbol(..))) {
+            Some(Datum::Symbol(token, _)) => Some(token),
+            _ => None,
+        }
+    }
+
+    /// Consume the next item if it is the symbol `keyword`
+    fn next_keyword(&mut self, keyword: &str) -> Option<Token> {
+        match self
+            .items
+            .next_if(|item| matches!(item, Datum::Symbol(_, sym) if sym == keyword))
+        {
+            Some(Datum::Symbol(token, _)) => Some(token),
+            _ => None,
+        }
+    }
+}
+
+/// Get the first token of `datum` in source order
+fn first_token(datum: &Datum) -> &Token {
+    match datum {
+        Datum::Symbol(token, _)
+        | Datum::Number(token, _)
+        | Datum::String(token, _)
+        | Datum::Bool(token, _)
+        | Datum::List(token, _, _)
+        | Datum::Quote(token, _) => token,
+    }
+}
+
+/// Syntax pass state
+///
+/// Contains the diagnostics produced so far and the macros which uses
+/// are expanded with.
+pub(crate) struct Syntax<'m> {
+    diagnostics: Vec<Diagnostic>,
+    macros: &'m mut Macros,
+    expanded: usize,
+    depth: usize,
+}
+
+impl<'m> Syntax<'m> {
+    /// Create a syntax pass which expands uses of `macros`, and adds
+    /// any macros defined by the code it converts
+    pub(crate) fn new(macros: &'m mut Macros) -> Self {
+        Syntax {
+            diagnostics: Vec::new(),
+            macros,
+            expanded: 0,
+            depth: 0,
+        }
+    }
+
+    /// Get the diagnostics produced so far
+    pub(crate) fn into_diagnostics(self) -> Vec<Diagnostic> {
+        self.diagnostics
+    }
+
+    /// Record a diagnostic. A single problem can cause several parts
+    /// of a form to fail at the same token, so only the first
+    /// diagnostic at any given location is kept.
+    fn error(&mut self, code: ErrorCode, span: Span, message: String) {
+        if self.diagnostics.last().map(|d| d.span) != Some(span) {
+            self.diagnostics.push(Diagnostic::new(code, span, message));
+        }
+    }
+
+    /// Record an error for the next of the `items`, or for the bracket
+    /// which closes them if there are none left
+    fn error_at_peek(&mut self, items: &mut Items, code: ErrorCode, expected: &str) {
+        let token = items.peek_token();
+        let code = match token.kind {
+            TokenKind::EndOfFile => ErrorCode::UnexpectedEndOfFile,
+            _ => code,
+        };
+        let span = token.span();
+        let message = format!("expected {}, found {}", expected, token.kind);
+        self.error(code, span, message);
+    }
+
+    /// Convert a datum into an expression
+    ///
+    /// If the datum isn't a valid expression then a diagnostic is
+    /// recorded and `None` is returned.
+    pub(crate) fn expr(&mut self, datum: Datum) -> Option<Expr> {
+        match datum {
+            Datum::List(open, items, close) => self.parse_form(open, Items::new(items, close)),
+            Datum::Quote(quote, datum) => Some(Expr::QuoteAbbrev(quote, datum)),
+            Datum::Number(token, n) => Some(Expr::Number(token, n)),
+            Datum::Bool(token, b) => Some(Expr::Bool(token, b)),
+            Datum::String(token, s) => Some(Expr::String(token, s)),
+            Datum::Symbol(token, sym) => Some(Expr::Symbol(token, sym)),
+        }
+    }
+
+    /// Convert the next of the `items` into an expression, or record a
+    /// diagnostic if there are none left
+    fn parse_expr(&mut self, items: &mut Items) -> Option<Expr> {
+        match items.next() {
+            Some(datum) => self.expr(datum),
+            None => {
+                self.error_at_peek(items, ErrorCode::UnexpectedToken, "expression");
+                None
+            }
+        }
+    }
+
+    // Parse one of our recognised strucutred forms beginning with the
+    // given token
+    fn parse_form(&mut self, open: Token, mut items: Items) -> Option<Expr> {
+        let name = match items.peek() {
+            Some(Datum::Symbol(_, sym)) => sym.clone(),
+            Some(_) => return self.parse_call(open, items),
+            None => {
+                self.error_at_peek(
+                    &mut items,
+                    ErrorCode::InvalidForm,
+                    "function or special form",
+                );
+                return None;
+            }
+        };
+        match &name[..] {
+            "if" => self.parse_if(open, items),
+            "cond" => self.parse_cond(open, items),
+            "case" => self.parse_case(open, items),
+            "when" | "unless" => self.parse_when_unless(open, items),
+            "define" | "set!" => self.parse_define(open, items),
+            "lambda" => self.parse_lambda(open, items),
+            "let" | "let*" | "letrec" | "letrec*" => self.parse_let(open, items),
+            "module" => self.parse_module(open, items),
+            "import" => self.parse_import(open, items),
+            "define-syntax" => self.parse_define_syntax(open, items),
+            "defmacro" => self.parse_defmacro(open, items),
+            "quote" => self.parse_quote(open, items),
+            "and" | "or" => self.parse_and_or(open, items),
+            sym if self.macros.contains(sym) => self.expand_macro(open, items),
+            _ => self.parse_call(open, items),
+        }
+    }
+
+    /// Parse an `if` form, given its opening bracket. The `else`
+    /// branch is optional.
+    fn parse_if(&mut self, open: Token, mut items: Items) -> Option<Expr> {
+        let if_tok = items.next_symbol().unwrap();
+        let cond = self.parse_expr(&mut items);
+        let if_true = self.parse_expr(&mut items);
+        let if_false = match items.at_close() {
+            true => Some(None),
+            false => self.parse_expr(&mut items).map(|expr| Some(Box::new(expr))),
+        };
+        let close = self.expect_close(items);
+        Some(Expr::If(
+            open,
+            if_tok,
+            Box::new(cond?),
+            Box::new(if_true?),
+            if_false?,
+            close?,
+        ))
+    }
+
+    /// Parse a `cond` form, given its opening bracket. An `else`
+    /// clause must be the last one.
+    fn parse_cond(&mut self, open: Token, mut items: Items) -> Option<Expr> {
+        let cond_tok = items.next_symbol().unwrap();
+        let mut clauses = Vec::new();
+        while !items.at_close() {
+            let clause = self.parse_cond_clause(&mut items);
+            let is_else = matches!(clause, Some(ast::CondClause::Else(..)));
+            clauses.push(clause);
+            if is_else {
+                break;
+            }
+        }
+        let close = self.expect_close(items);
+        let clauses = clauses.into_iter().collect::<Option<Vec<_>>>();
+        Some(Expr::Cond(open, cond_tok, clauses?, close?))
+    }
+
+    /// Parse a single clause of a `cond` form
+    fn parse_cond_clause(&mut self, items: &mut Items) -> Option<ast::CondClause> {
+        let (open, mut clause) = self.expect_list(items)?;
+        if let Some(else_tok) = clause.next_keyword("else") {
+            let body = self.parse_function_body(&mut clause);
+            let close = self.expect_close(clause);
+            return Some(ast::CondClause::Else(open, else_tok, body?, close?));
+        }
+        let test = self.parse_expr(&mut clause);
+        if let Some(arrow_tok) = clause.next_keyword("=>") {
+            let receiver = self.parse_expr(&mut clause);
+            let close = self.expect_close(clause);
+            return Some(ast::CondClause::Arrow(
+                open,
+                Box::new(test?),
+                arrow_tok,
+                Box::new(receiver?),
+                close?,
+            ));
+        }
+        let body = self.parse_body(&mut clause);
+        let close = self.expect_close(clause);
+        Some(ast::CondClause::Test(open, Box::new(test?), body?, close?))
+    }
+
+    /// Parse a `case` form, given its opening bracket. An `else`
+    /// clause must be the last one.
+    fn parse_case(&mut self, open: Token, mut items: Items) -> Option<Expr> {
+        let case_tok = items.next_symbol().unwrap();
+        let key = self.parse_expr(&mut items);
+        let mut clauses = Vec::new();
+        while !items.at_close() {
+            let clause = self.parse_case_clause(&mut items);
+            let is_else = matches!(clause, Some(ast::CaseClause::Else(..)));
+            clauses.push(clause);
+            if is_else {
+                break;
+            }
+        }
+        let close = self.expect_close(items);
+        let clauses = clauses.into_iter().collect::<Option<Vec<_>>>();
+        Some(Expr::Case(open, case_tok, Box::new(key?), clauses?, close?))
+    }
+
+    /// Parse a single clause of a `case` form. The data a clause lists
+    /// aren't interpreted as code, so any symbols in them which macro
+    /// expansion renamed are given back their original names.
+    fn parse_case_clause(&mut self, items: &mut Items) -> Option<ast::CaseClause> {
+        let (open, mut clause) = self.expect_list(items)?;
+        if let Some(else_tok) = clause.next_keyword("else") {
+            let body = self.parse_function_body(&mut clause);
+            let close = self.expect_close(clause);
+            return Some(ast::CaseClause::Else(open, else_tok, body?, close?));
+        }
+        let (data_open, mut data_items) = self.expect_list(&mut clause)?;
+        let data = data_items.items.by_ref().map(expand::unrename).collect();
+        let data_close = self.expect_close(data_items);
+        let body = self.parse_function_body(&mut clause);
+        let close = self.expect_close(clause);
+        Some(ast::CaseClause::Data(
+            open,
+            data_open,
+            data,
+            data_close?,
+            body?,
+            close?,
+        ))
+    }
+
+    /// Parse a `when` or `unless` form, given its opening bracket
+    fn parse_when_unless(&mut self, open: Token, mut items: Items) -> Option<Expr> {
+        let kw_tok = items.next_symbol().unwrap();
+        let cond = self.parse_expr(&mut items);
+        let body = self.parse_function_body(&mut items);
+        let close = self.expect_close(items);
+        let (cond, body, close) = (Box::new(cond?), body?, close?);
+        Some(if kw_tok.text() == "when" {
+            Expr::When(open, kw_tok, cond, body, close)
+        } else {
+            Expr::Unless(open, kw_tok, cond, body, close)
+        })
+    }
+
+    /// Parse a `define` or `set!` form, given its opening bracket
+    fn parse_define(&mut self, open: Token, mut items: Items) -> Option<Expr> {
+        let define_tok = items.next_symbol().unwrap();
+        let sym_tok = self.expect_symbol(&mut items);
+        let value = self.parse_expr(&mut items);
+        let close = self.expect_close(items);
+        let (sym_tok, value, close) = (sym_tok?, Box::new(value?), close?);
+        Some(if define_tok.text() == "define" {
+            Expr::Define(open, define_tok, sym_tok, value, close)
+        } else {
+            Expr::Set(open, define_tok, sym_tok, value, close)
+        })
+    }
+
+    /// Parse a `lambda` form, given its opening bracket. The parameter
+    /// list must be followed by at least one body expression.
+    fn parse_lambda(&mut self, open: Token, mut items: Items) -> Option<Expr> {
+        let lambda_tok = items.next_symbol().unwrap();
+        let (params_open, params, params_close) = self.parse_params(&mut items)?;
+        let body = self.parse_function_body(&mut items);
+        let close = self.expect_close(items);
+        Some(Expr::Lambda(
+            open,
+            lambda_tok,
+            params_open,
+            params?,
+            params_close?,
+            body?.into(),
+            close?,
+        ))
+    }
+
+    /// Parse a `let`, `let*`, `letrec` or `letrec*` form, given its
+    /// opening bracket. A `let` with a name before its bindings is a
+    /// named let.
+    fn parse_let(&mut self, open: Token, mut items: Items) -> Option<Expr> {
+        let let_tok = items.next_symbol().unwrap();
+        let keyword = let_tok.text().to_owned();
+        let name = match &keyword[..] {
+            "let" => items.next_symbol(),
+            _ => None,
+        };
+        let bindings = self.parse_let_bindings(&mut items, keyword != "let*");
+        let body = self.parse_function_body(&mut items);
+        let close = self.expect_close(items);
+        let (bindings, body, close) = (Box::new(bindings?), body?, close?);
+        Some(match (&keyword[..], name) {
+            (_, Some(name)) => Expr::NamedLet(open, let_tok, name, bindings, body.into(), close),
+            ("let", None) => Expr::Let(open, let_tok, bindings, body, close),
+            ("let*", None) => Expr::LetStar(open, let_tok, bindings, body, close),
+            _ => Expr::Letrec(open, let_tok, bindings, body, close),
+        })
+    }
+
+    /// Parse the `((<symbol> <expr>)...)` bindings of a `let` form. If
+    /// they must be `unique` then a name bound twice is reported.
+    fn parse_let_bindings(&mut self, items: &mut Items, unique: bool) -> Option<ast::LetBindings> {
+        let (open, mut list) = self.expect_list(items)?;
+        let mut bindings = Vec::<Option<ast::LetBinding>>::new();
+        while !list.at_close() {
+            let binding = self.parse_let_binding(&mut list);
+            if let (true, Some(binding)) = (unique, &binding) {
+                let names = bindings.iter().flatten().map(|b| b.name());
+                self.check_unique(names, binding.name());
+            }
+            bindings.push(binding);
+        }
+        let close = self.expect_close(list);
+        let bindings = bindings.into_iter().collect::<Option<Vec<_>>>();
+        Some(ast::LetBindings(open, bindings?, close?))
+    }
+
+    /// Parse a single `(<symbol> <expr>)` binding of a `let` form
+    fn parse_let_binding(&mut self, items: &mut Items) -> Option<ast::LetBinding> {
+        let (open, mut pair) = self.expect_list(items)?;
+        let name = self.expect_symbol(&mut pair);
+        let value = self.parse_expr(&mut pair);
+        let close = self.expect_close(pair);
+        Some(ast::LetBinding(open, name?, value?, close?))
+    }
+
+    /// Parse a `defmacro` form, given its opening bracket. The macro
+    /// is defined straight away, so it can be used by the rest of the
+    /// source.
+    fn parse_defmacro(&mut self, open: Token, mut items: Items) -> Option<Expr> {
+        let defmacro_tok = items.next_symbol().unwrap();
+        let name = self.expect_symbol(&mut items);
+        let (params_open, params, params_close) = self.parse_params(&mut items)?;
+        let body = self.parse_function_body(&mut items);
+        let close = self.expect_close(items);
+        let (name, params, body) = (name?, params?, Rc::<[Expr]>::from(body?));
+        if !self.check_macro_name(&name) {
+            return None;
+        }
+        self.macros.define_procedure(
+            name.text().to_owned(),
+            params.iter().map(|param| param.text().to_owned()).collect(),
+            Rc::clone(&body),
+        );
+        Some(Expr::DefMacro(
+            open,
+            defmacro_tok,
+            name,
+            params_open,
+            params,
+            params_close?,
+            body,
+            close?,
+        ))
+    }
+
+    /// Parse the parameter list of a function, `(<symbol>...)`
+    ///
+    /// If the next item isn't a list then `None` is returned.
+    fn parse_params(
+        &mut self,
+        items: &mut Items,
+    ) -> Option<(Token, Option<Vec<Token>>, Option<Token>)> {
+        let (params_open, mut params_items) = self.expect_list(items)?;
+        let mut params = Vec::new();
+        while !params_items.at_close() {
+            let param = self.expect_symbol(&mut params_items);
+            if let Some(param) = &param {
+                self.check_unique(params.iter().flatten(), param);
+            }
+            params.push(param);
+        }
+        let params_close = self.expect_close(params_items);
+        let params = params.into_iter().collect::<Option<Vec<_>>>();
+        Some((params_open, params, params_close))
+    }
+
+    /// Parse the body of a function, which must contain at least one
+    /// expression
+    fn parse_function_body(&mut self, items: &mut Items) -> Option<Vec<Expr>> {
+        let body = self.parse_body(items)?;
+        if body.is_empty() {
+            self.error_at_peek(items, ErrorCode::UnexpectedToken, "expression");
+            return None;
+        }
+        Some(body)
+    }
+
+    /// Parse a `quote` form, given its opening bracket
+    fn parse_quote(&mut self, open: Token, mut items: Items) -> Option<Expr> {
+        let quote_tok = items.next_symbol().unwrap();
+        let datum = self.parse_datum(&mut items);
+        let close = self.expect_close(items);
+        Some(Expr::Quote(open, quote_tok, Box::new(datum?), close?))
+    }
+
+    /// Parse an `and` or `or` form, given its opening bracket
+    fn parse_and_or(&mut self, open: Token, mut items: Items) -> Option<Expr> {
+        let kw_tok = items.next_symbol().unwrap();
+        let body = self.parse_body(&mut items);
+        let close = self.expect_close(items);
+        let (body, close) = (body?, close?);
+        Some(if kw_tok.text() == "and" {
+            Expr::And(open, kw_tok, body, close)
+        } else {
+            Expr::Or(open, kw_tok, body, close)
+        })
+    }
+
+    /// Parse a `module` form, given its opening bracket. The module
+    /// name is followed by its `(export <symbol>...)` list and then its
+    /// body.
+    fn parse_module(&mut self, open: Token, mut items: Items) -> Option<Expr> {
+        let module_tok = items.next_symbol().unwrap();
+        let name = self.expect_symbol(&mut items);
+        let (exports_open, mut export_items) = self.expect_list(&mut items)?;
+        let export_tok = self.expect_keyword(&mut export_items, "export")?;
+        let mut exports = Vec::new();
+        while !export_items.at_close() {
+            exports.push(self.expect_symbol(&mut export_items));
+        }
+        let exports_close = self.expect_close(export_items);
+        let body = self.parse_body(&mut items);
+        let close = self.expect_close(items);
+        let exports = exports.into_iter().collect::<Option<Vec<_>>>();
+        let exports = ast::Exports(exports_open, export_tok, exports?, exports_close?);
+        Some(Expr::Module(
+            open,
+            module_tok,
+            name?,
+            Box::new(exports),
+            body?,
+            close?,
+        ))
+    }
+
+    /// Parse an `import` form, given its opening bracket
+    fn parse_import(&mut self, open: Token, mut items: Items) -> Option<Expr> {
+        let import_tok = items.next_symbol().unwrap();
+        let name = self.expect_symbol(&mut items);
+        let mut options = Vec::new();
+        while !items.at_close() {
+            options.push(self.parse_import_option(&mut items));
+        }
+        let close = self.expect_close(items);
+        let options = options.into_iter().collect::<Option<Vec<_>>>();
+        Some(Expr::Import(open, import_tok, name?, options?, close?))
+    }
+
+    /// Parse a `define-syntax` form, given its opening bracket. The
+    /// macro is defined straight away, so it can be used by the rest of
+    /// the source.
+    fn parse_define_syntax(&mut self, open: Token, mut items: Items) -> Option<Expr> {
+        let define_tok = items.next_symbol().unwrap();
+        let name = self.expect_symbol(&mut items);
+        let rules = self.parse_datum(&mut items);
+        let close = self.expect_close(items);
+        let (name, rules) = (name?, rules?);
+        if !self.check_macro_name(&name) {
+            return None;
+        }
+        match SyntaxRules::new(&rules) {
+            Ok(rules) => self
+                .macros
+                .define(name.text().to_owned(), Macro::Rules(rules)),
+            Err(diagnostic) => {
+                self.error(diagnostic.code, diagnostic.span, diagnostic.message);
+                return None;
+            }
+        }
+        Some(Expr::DefineSyntax(
+            open,
+            define_tok,
+            name,
+            Box::new(rules),
+            close?,
+        ))
+    }
+
+    /// Check that `name` can be defined as a macro. Special forms
+    /// can't be redefined.
+    fn check_macro_name(&mut self, name: &Token) -> bool {
+        if SPECIAL_FORMS.contains(&name.text()) {
+            self.error(
+                ErrorCode::InvalidMacro,
+                name.span(),
+                format!("the special form `{}` can't be redefined", name.text()),
+            );
+            return false;
+        }
+        true
+    }
+
+    /// Take the next of the `items` as a datum, or record a diagnostic
+    /// if there are none left
+    ///
+    /// Data aren't interpreted as code, so special form names are just
+    /// symbols and lists can be empty.
+    fn parse_datum(&mut self, items: &mut Items) -> Option<Datum> {
+        let datum = items.next();
+        if datum.is_none() {
+            self.error_at_peek(items, ErrorCode::UnexpectedToken, "datum");
+        }
+        datum
+    }
+
+    /// Parse a function call. The first expression in the form is the
+    /// function to call, and the remaining ones are its arguments.
+    fn parse_call(&mut self, open: Token, mut items: Items) -> Option<Expr> {
+        let callee = self.parse_expr(&mut items);
+        let args = self.parse_body(&mut items);
+        let close = self.expect_close(items);
+        Some(Expr::Call(open, Box::new(callee?), args?, close?))
+    }
+
+    /// Expand a use of a macro, then convert the expansion in its place
+    ///
+    /// The use is passed to the macro as data, so its arguments don't
+    /// have to be valid expressions. The expansion keeps the location
+    /// of the use, so errors in it can point back there.
+    fn expand_macro(&mut self, open: Token, mut items: Items) -> Option<Expr> {
+        let name = items.peek_token().text().to_string();
+        let args = items.items.by_ref().collect();
+        let close = self.expect_close(items)?;
+        let form = Datum::List(open, args, close);
+        let expansion = match self.macros.expand(&form) {
+            Ok(expansion) => expansion,
+            Err(diagnostic) => {
+                self.error(diagnostic.code, diagnostic.span, diagnostic.message);
+                return None;
+            }
+        };
+        self.expanded += expand::count_tokens(&expansion);
+        let problem = if self.expanded > EXPANSION_LIMIT {
+            Some("too large")
+        } else if self.depth >= EXPANSION_DEPTH_LIMIT {
+            Some("nested too deeply")
+        } else {
+            None
+        };
+        if let Some(problem) = problem {
+            self.error(
+                ErrorCode::InvalidMacro,
+                form.span(),
+                format!("macro expansion is {}, it may never finish", problem),
+            );
+            return None;
+        }
+        self.depth += 1;
+        let expr = self.expr(expansion);
+        self.depth -= 1;
+        Some(Expr::Expansion(form.span(), name, Box::new(expr?)))
+    }
+
+    /// Parse one of the options of an `import` form, either
+    /// `(prefix <symbol>)` or `(rename (<from> <to>)...)`
+    fn parse_import_option(&mut self, items: &mut Items) -> Option<ast::ImportOption> {
+        let (open, mut option) = self.expect_list(items)?;
+        if let Some(prefix_tok) = option.next_keyword("prefix") {
+            let prefix = self.expect_symbol(&mut option);
+            let close = self.expect_close(option);
+            Some(ast::ImportOption::Prefix(open, prefix_tok, prefix?, close?))
+        } else if let Some(rename_tok) = option.next_keyword("rename") {
+            let mut renames = Vec::new();
+            while !option.at_close() {
+                renames.push(self.parse_rename(&mut option));
+            }
+            let close = self.expect_close(option);
+            let renames = renames.into_iter().collect::<Option<Vec<_>>>();
+            Some(ast::ImportOption::Rename(
+                open, rename_tok, renames?, close?,
+            ))
+        } else {
+            self.error_at_peek(&mut option, ErrorCode::InvalidForm, "`prefix` or `rename`");
+            None
+        }
+    }
+
+    /// Parse a single `(<from> <to>)` pair in a `rename` option
+    fn parse_rename(&mut self, items: &mut Items) -> Option<(Token, Token, Token, Token)> {
+        let (open, mut pair) = self.expect_list(items)?;
+        let from = self.expect_symbol(&mut pair);
+        let to = self.expect_symbol(&mut pair);
+        let close = self.expect_close(pair);
+        Some((open, from?, to?, close?))
+    }
+
+    /// Parse the remaining items as a sequence of expressions. Returns
+    /// `None` if any of the expressions are invalid.
+    fn parse_body(&mut self, items: &mut Items) -> Option<Vec<Expr>> {
+        let mut body = Vec::new();
+        while let Some(datum) = items.next() {
+            body.push(self.expr(datum));
+        }
+        body.into_iter().collect()
+    }
+
+    /// Consume a nested list, or record a diagnostic if the next item
+    /// isn't a list. Any other item in its place is skipped.
+    fn expect_list(&mut self, items: &mut Items) -> Option<(Token, Items)> {
+        match items.items.next_if(|item| matches!(item, Datum::List(..))) {
+            Some(Datum::List(open, list, close)) => Some((open, Items::new(list, close))),
+            _ => {
+                self.error_at_peek(items, ErrorCode::UnexpectedToken, "`(`");
+                items.next();
+                None
+            }
+        }
+    }
+
+    /// Consume a symbol, or record a diagnostic if the next item isn't
+    /// a symbol. Any other item in its place is skipped.
+    fn expect_symbol(&mut self, items: &mut Items) -> Option<Token> {
+        let symbol = items.next_symbol();
+        if symbol.is_none() {
+            self.error_at_peek(items, ErrorCode::ExpectedSymbol, "symbol");
+            items.next();
+        }
+        symbol
+    }
+
+    /// Record a diagnostic if `name` was already bound by one of the
+    /// `earlier` names of the same list
+    fn check_unique<'a>(&mut self, mut earlier: impl Iterator<Item = &'a Token>, name: &Token) {
+        if earlier.any(|other| other.text() == name.text()) {
+            let message = format!("`{}` is bound more than once", name.text());
+            self.error(ErrorCode::DuplicateBinding, name.span(), message);
+        }
+    }
+
+    /// Consume the symbol `keyword`, or record a diagnostic if the
+    /// next item is anything else
+    fn expect_keyword(&mut self, items: &mut Items, keyword: &str) -> Option<Token> {
+        let token = items.next_keyword(keyword);
+        if token.is_none() {
+            self.error_at_peek(items, ErrorCode::UnexpectedToken, &format!("`{}`", keyword));
+        }
+        token
+    }
+
+    /// Finish a form, returning its closing bracket. If there are
+    /// items left over, or the list was never closed, a diagnostic is
+    /// recorded instead.
+    fn expect_close(&mut self, mut items: Items) -> Option<Token> {
+        if items.at_close() && items.close.kind == TokenKind::RightBracket {
+            Some(items.close)
+        } else {
+            self.error_at_peek(&mut items, ErrorCode::UnexpectedToken, "`)`");
+            None
+        }
+    }
+}
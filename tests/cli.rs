@@ -0,0 +1,202 @@
+//! Tests for the command line options of the `formula-one` binary
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+/// Run the binary with `args`, and `input` as its standard input
+fn run(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_formula-one"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("could not start formula-one");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// Create an empty directory for the files of the test `name`. The
+/// directory is unique to this run of the tests.
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("formula-one-cli-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Write `source` to the script file `name` in `dir`
+fn script(dir: &Path, name: &str, source: &str) -> String {
+    let path = dir.join(name);
+    std::fs::write(&path, source).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+#[test]
+fn cli_evaluates_expressions() {
+    let output = run(&["-e", "(+ 1 2)", "-e", "(list 1 2)"], "");
+    assert!(output.status.success());
+    assert_eq!(
+        " ~> 3\n ~> (1 2)\n",
+        String::from_utf8_lossy(&output.stdout)
+    );
+}
+
+#[test]
+fn cli_reads_scripts_from_stdin() {
+    let output = run(&["-"], "(define x 10)\n(* x 2)\n");
+    assert!(output.status.success());
+    assert_eq!(" ~> 20\n", String::from_utf8_lossy(&output.stdout));
+}
+
+#[test]
+fn cli_skips_shebang_lines() {
+    let dir = test_dir("shebang");
+    let path = script(
+        &dir,
+        "shebang.f1",
+        "#!/usr/bin/env formula-one\n(print \"hi\")\n",
+    );
+    let output = run(&["--quiet", &path], "");
+    assert!(output.status.success());
+    assert_eq!("hi\n", String::from_utf8_lossy(&output.stdout));
+}
+
+#[test]
+fn cli_passes_script_arguments() {
+    let output = run(&["-q", "-", "--", "one", "-e", "3"], "(print command-line)");
+    assert!(output.status.success());
+    assert_eq!(
+        "(\"one\" \"-e\" \"3\")\n",
+        String::from_utf8_lossy(&output.stdout)
+    );
+
+    let output = run(&["-e", "command-line"], "");
+    assert_eq!(" ~> nil\n", String::from_utf8_lossy(&output.stdout));
+
+    // Everything after a `--script` is an argument to it, as when an
+    // executable script is run through its `#!` line
+    let dir = test_dir("script-arguments");
+    let path = script(
+        &dir,
+        "args.f1",
+        "#!/usr/bin/env -S formula-one --script\n(print command-line)\n",
+    );
+    let output = run(&["-q", "--script", &path, "foo", "-q", "bar.f1"], "");
+    assert!(output.status.success());
+    assert_eq!(
+        "(\"foo\" \"-q\" \"bar.f1\")\n",
+        String::from_utf8_lossy(&output.stdout)
+    );
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let source = format!(
+            "#!{} --script\n(print command-line)\n",
+            env!("CARGO_BIN_EXE_formula-one")
+        );
+        let path = script(&dir, "run.f1", &source);
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let output = Command::new(&path).args(["foo", "bar"]).output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(
+            "(\"foo\" \"bar\")\n ~> (\"foo\" \"bar\")\n",
+            String::from_utf8_lossy(&output.stdout)
+        );
+    }
+}
+
+#[test]
+fn cli_fails_on_errors() {
+    let output = run(&["-e", "(car 1)", "-e", "(print 2)"], "");
+    assert_eq!(Some(1), output.status.code());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("error[E0002]: expected pair, found number `1`"));
+    assert!(stderr.contains("<expr>:1:1"));
+
+    let output = run(&["-"], "(+ 1");
+    assert_eq!(Some(1), output.status.code());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("error[P0003]"));
+
+    let output = run(&["-e", "(define f (lambda (n) (+ 1 (f n)))) (f 1)"], "");
+    assert_eq!(Some(1), output.status.code());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("error[E0009]: recursion too deep"));
+    assert!(stderr.contains("more calls"));
+
+    let output = run(&["no-such-file.f1"], "");
+    assert_eq!(Some(1), output.status.code());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("could not read `no-such-file.f1`"));
+}
+
+#[test]
+fn cli_reports_usage_errors() {
+    let output = run(&["--frobnicate"], "");
+    assert_eq!(Some(2), output.status.code());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown option `--frobnicate`"));
+
+    let output = run(&["-e"], "");
+    assert_eq!(Some(2), output.status.code());
+    let output = run(&["--script"], "");
+    assert_eq!(Some(2), output.status.code());
+
+    let output = run(&["--help"], "");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("Usage: formula-one"));
+}
+
+#[test]
+fn cli_inputs_share_an_environment() {
+    let dir = test_dir("shared-environment");
+    let lib = script(&dir, "lib.f1", "(define double (lambda (x) (* 2 x)))");
+    let main = script(&dir, "main.f1", "(print (double 21))");
+    let output = run(&["-q", &lib, &main], "");
+    assert!(output.status.success());
+    assert_eq!("42\n", String::from_utf8_lossy(&output.stdout));
+
+    let output = run(&["-q", &lib, "-e", "(print (double 4))"], "");
+    assert_eq!("8\n", String::from_utf8_lossy(&output.stdout));
+
+    let output = run(&["--isolate", &lib, &main], "");
+    assert_eq!(Some(1), output.status.code());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("undefined symbol `double`"));
+}
+
+#[test]
+fn cli_searches_module_directories() {
+    let dir = test_dir("modules");
+    std::fs::write(
+        dir.join("greet.f1"),
+        "(module greet (export hello)) (define hello \"hi\")",
+    )
+    .unwrap();
+    let dir = dir.to_string_lossy();
+
+    let output = run(&["-I", &dir, "-e", "(import greet) hello"], "");
+    assert!(output.status.success());
+    assert_eq!(" ~> hi\n", String::from_utf8_lossy(&output.stdout));
+
+    let output = run(&["-e", "(import greet)"], "");
+    assert_eq!(Some(1), output.status.code());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("could not find module `greet`"));
+}
+
+#[test]
+fn cli_prints_expanded_programs() {
+    let output = run(
+        &["--expand", "-"],
+        "(defmacro twice (e) (list 'begin e e))\n(twice (print 1))\n",
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        "(defmacro twice (e) (list 'begin e e))\n(begin (print 1) (print 1))\n1\n1\n ~> 1\n",
+        String::from_utf8_lossy(&output.stdout)
+    );
+}
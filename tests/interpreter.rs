@@ -0,0 +1,363 @@
+//! Integration tests for the public embedding API
+
+use formula_one::*;
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+fn expect_number(result: Result<Value, Error>) -> i64 {
+    match result {
+        Ok(Value::Number(n)) => n,
+        other => panic!("expected number, found {:?}", other),
+    }
+}
+
+#[test]
+fn interpreter_keeps_definitions() {
+    let mut interpreter = Interpreter::new();
+    interpreter.eval_str("(define foo 1007)").unwrap();
+    interpreter.eval_str("(define bar 330)").unwrap();
+    assert_eq!(1337, expect_number(interpreter.eval_str("(+ foo bar)")));
+    assert_eq!(
+        1007,
+        expect_number(Ok(interpreter.get("foo").expect("foo is defined")))
+    );
+}
+
+#[test]
+fn interpreter_lists_globals() {
+    let mut interpreter = Interpreter::new();
+    interpreter
+        .eval_str("(define zzz 1) (define aaa 2)")
+        .unwrap();
+    let globals = interpreter.globals();
+    let names = globals
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<_>>();
+    assert!(names.contains(&"+"));
+    assert_eq!(Some(&"zzz"), names.last());
+    assert!(names.windows(2).all(|w| w[0] < w[1]));
+}
+
+#[test]
+fn interpreter_evaluates_parsed_programs() {
+    let program = parse("(define x 20) (* x 2)").unwrap();
+    assert_eq!(2, program.forms().len());
+    let mut interpreter = Interpreter::default();
+    assert_eq!(
+        40,
+        expect_number(interpreter.eval(&program).map_err(Error::Eval))
+    );
+}
+
+#[test]
+fn interpreter_reruns_parsed_programs() {
+    let program = parse("(set! counter (+ counter 1)) counter").unwrap();
+    let mut interpreter = Interpreter::new();
+    interpreter.define("counter", Value::Number(0));
+    for expected in 1..=3 {
+        assert_eq!(
+            expected,
+            expect_number(interpreter.eval(&program).map_err(Error::Eval))
+        );
+    }
+}
+
+#[test]
+fn interpreter_reports_errors() {
+    let mut interpreter = Interpreter::new();
+    match interpreter.eval_str("(+ 1") {
+        Err(Error::Parse(diagnostics)) => {
+            assert_eq!(1, diagnostics.len());
+            assert_eq!(ErrorCode::UnexpectedEndOfFile, diagnostics[0].code);
+        }
+        other => panic!("expected parse error, found {:?}", other),
+    }
+    assert!(matches!(
+        interpreter.eval_str("undefined-symbol"),
+        Err(Error::Eval(_))
+    ));
+}
+
+#[test]
+fn tokens_expose_spans() {
+    let tokens = tokenise("(foo 12)");
+    let spans: Vec<_> = tokens.iter().map(Token::span).collect();
+    assert_eq!(
+        vec![
+            Span::new(ByteIndex(1), ByteIndex(2)),
+            Span::new(ByteIndex(2), ByteIndex(5)),
+            Span::new(ByteIndex(6), ByteIndex(8)),
+            Span::new(ByteIndex(8), ByteIndex(9)),
+            Span::new(ByteIndex(9), ByteIndex(9)),
+        ],
+        spans
+    );
+    assert_eq!(TokenKind::Number(12), tokens[2].kind);
+}
+
+#[test]
+fn native_functions_can_be_registered() {
+    let mut interpreter = Interpreter::new();
+    interpreter.define_native(NativeFunction::wrap("add", |a: i64, b: i64| a + b));
+
+    let calls = Rc::new(Cell::new(0));
+    let counter = Rc::clone(&calls);
+    interpreter.define_native(NativeFunction::wrap("tick", move || {
+        counter.set(counter.get() + 1);
+        counter.get()
+    }));
+
+    assert_eq!(5, expect_number(interpreter.eval_str("(add 2 3)")));
+    interpreter.eval_str("(tick) (tick)").unwrap();
+    assert_eq!(2, calls.get());
+    match interpreter.eval_str("(add 1)") {
+        Err(Error::Eval(error)) => assert_eq!(
+            &EvalErrorKind::ArityMismatch {
+                name: "add".into(),
+                expected: Arity::Exact(2),
+                found: 1
+            },
+            error.kind()
+        ),
+        other => panic!("expected arity error, found {:?}", other),
+    }
+}
+
+#[test]
+fn errors_render_source_snippets() {
+    let source = "(define f (lambda (x) (string-length x)))\n(f 1)\n";
+    let error = Interpreter::new().eval_str(source).unwrap_err();
+    assert_eq!(
+        "error[E0002]: expected string, found number `1`
+  ┌─ test.f1:1:23
+  │
+1 │ (define f (lambda (x) (string-length x)))
+  │                       ^^^^^^^^^^^^^^^^^
+2 │ (f 1)
+  │ ----- in call to `f`
+
+",
+        error.render("test.f1", source)
+    );
+
+    let source = "(+ 1 2))";
+    let error = Interpreter::new().eval_str(source).unwrap_err();
+    assert_eq!(
+        "error[P0002]: unexpected `)` with no matching `(`
+  ┌─ test.f1:1:8
+  │
+1 │ (+ 1 2))
+  │        ^
+
+",
+        error.render("test.f1", source)
+    );
+}
+
+#[test]
+fn load_resolves_paths_relative_to_the_loading_file() {
+    let dir = std::env::temp_dir().join(format!("formula-one-load-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("lib")).unwrap();
+    std::fs::write(
+        dir.join("lib/helpers.f1"),
+        "(load \"more.f1\") (define double (lambda (x) (* more x)))",
+    )
+    .unwrap();
+    std::fs::write(dir.join("lib/more.f1"), "(define more 2)").unwrap();
+    std::fs::write(dir.join("cycle.f1"), "(load \"cycle.f1\")").unwrap();
+
+    let mut interpreter = Interpreter::new();
+    let main = dir.join("main.f1");
+    let result = interpreter.eval_file(&main, "(load \"lib/helpers.f1\") (double 21)");
+    assert_eq!(42, expect_number(result));
+    assert!(interpreter.get("more").is_some());
+
+    // Loading a file again only replaces its definitions when the
+    // interpreter allows redefinition
+    std::fs::write(dir.join("lib/more.f1"), "(define more 3)").unwrap();
+    match interpreter.eval_file(&main, "(load \"lib/helpers.f1\")") {
+        Err(Error::Eval(error)) => {
+            assert!(error.to_string().contains("`more` is already defined"));
+        }
+        other => panic!("expected a load error, found {:?}", other),
+    }
+    interpreter.allow_redefinition(true);
+    let result = interpreter.eval_file(&main, "(load \"lib/helpers.f1\") (double 21)");
+    assert_eq!(63, expect_number(result));
+
+    match interpreter.eval_file(&main, "(load \"cycle.f1\")") {
+        Err(Error::Eval(error)) => {
+            let message = error.to_string();
+            assert!(message.starts_with("error[E0006]: load: error in `cycle.f1`"));
+            assert!(message.contains("load: `cycle.f1` is already being loaded"));
+        }
+        other => panic!("expected a load error, found {:?}", other),
+    }
+
+    // Without a file, paths are relative to the working directory
+    match interpreter.eval_str("(load \"lib/more.f1\")") {
+        Err(Error::Eval(error)) => {
+            assert!(error.to_string().contains("could not read `lib/more.f1`"));
+        }
+        other => panic!("expected a load error, found {:?}", other),
+    }
+}
+
+#[test]
+fn read_file_returns_data_without_evaluating_it() {
+    let dir = std::env::temp_dir().join("formula-one-read-file");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("data.f1"), "(if) ; not code\nname 'x (1 2)").unwrap();
+    std::fs::write(dir.join("bad.f1"), "(a b").unwrap();
+
+    let mut interpreter = Interpreter::new();
+    let main = dir.join("main.f1");
+    match interpreter.eval_file(&main, "(read-file \"data.f1\")") {
+        Ok(value) => assert_eq!("((if) name (quote x) (1 2))", value.to_string()),
+        other => panic!("expected a list of data, found {:?}", other),
+    }
+    match interpreter.eval_file(&main, "(read-file \"bad.f1\")") {
+        Err(Error::Eval(error)) => {
+            let message = error.to_string();
+            assert!(message.contains("read-file: error in `bad.f1`"));
+            assert!(message.contains("expected `)`, found end of file"));
+        }
+        other => panic!("expected a read error, found {:?}", other),
+    }
+}
+
+#[test]
+fn modules_are_found_on_the_search_path() {
+    let dir = std::env::temp_dir().join(format!("formula-one-modules-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("lib")).unwrap();
+    std::fs::write(
+        dir.join("counter.f1"),
+        "(module counter (export next))
+         (import step)
+         (define next (lambda (n) (+ n step)))",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("lib/step.f1"),
+        "(module step (export step) (define step 2))",
+    )
+    .unwrap();
+    std::fs::write(dir.join("a.f1"), "(module a (export)) (import b)").unwrap();
+    std::fs::write(dir.join("b.f1"), "(module b (export)) (import a)").unwrap();
+
+    let mut interpreter = Interpreter::new();
+    interpreter.add_module_path(dir.join("lib"));
+    assert_eq!(vec![dir.join("lib")], interpreter.modules().search_path());
+    let main = dir.join("main.f1");
+    assert_eq!(
+        3,
+        expect_number(interpreter.eval_file(&main, "(import counter) (next 1)"))
+    );
+    assert_eq!(
+        4,
+        expect_number(interpreter.eval_file(&main, "(counter/next step/step)"))
+    );
+    let counter = interpreter.modules().get("counter").unwrap();
+    assert_eq!("counter", counter.name());
+    assert_eq!(&["next".to_string()], counter.exports());
+    // Module bodies don't leak into the global environment
+    assert!(interpreter.get("next").is_some());
+    assert!(interpreter.get("step").is_none());
+
+    match interpreter.eval_file(&main, "(import a)") {
+        Err(Error::Eval(error)) => {
+            assert!(error.to_string().contains("import: cycle a -> b -> a"));
+        }
+        other => panic!("expected an import cycle, found {:?}", other),
+    }
+    match interpreter.eval_file(&main, "(import missing)") {
+        Err(Error::Eval(error)) => assert_eq!(
+            &EvalErrorKind::Custom("import: could not find module `missing`".into()),
+            error.kind()
+        ),
+        other => panic!("expected a missing module, found {:?}", other),
+    }
+}
+
+#[test]
+fn macros_persist_between_evaluations() {
+    let mut interpreter = Interpreter::new();
+    interpreter
+        .eval_str(
+            "(define-syntax when-not
+               (syntax-rules ()
+                 ((_ c body ...) (if c '() (begin body ...)))))",
+        )
+        .unwrap();
+    assert_eq!(vec!["when-not".to_string()], interpreter.macros());
+    assert_eq!(
+        3,
+        expect_number(interpreter.eval_str("(when-not #f 1 2 3)"))
+    );
+    assert!(matches!(
+        interpreter.eval_str("(when-not #t 1)"),
+        Ok(Value::Nil)
+    ));
+}
+
+#[test]
+fn macros_can_call_earlier_definitions() {
+    let mut interpreter = Interpreter::new();
+    let result = interpreter.eval_file(
+        "main.f1",
+        "(define helper (lambda (x) (list '+ x 1)))
+         (defmacro m (x) (helper x))
+         (m 1)",
+    );
+    assert_eq!(2, expect_number(result));
+
+    let mut interpreter = Interpreter::new();
+    let mut forms = Vec::new();
+    let result = interpreter.eval_source(
+        None,
+        "(define twice (lambda (e) (list 'begin e e)))
+         (defmacro dup (e) (twice e))
+         (dup 7)",
+        |form| forms.push(form.to_string().trim().to_string()),
+    );
+    assert_eq!(7, expect_number(result));
+    assert_eq!(Some(&"(begin 7 7)".to_string()), forms.last());
+}
+
+#[test]
+fn default_recursion_limit_fits_a_default_stack() {
+    // Debug builds need several times the stack of release ones, so
+    // are checked against the size of the main thread instead
+    let stack = if cfg!(debug_assertions) { 8 } else { 2 } * 1024 * 1024;
+    let result = std::thread::Builder::new()
+        .stack_size(stack)
+        .spawn(|| {
+            let mut interpreter = Interpreter::new();
+            let sources = [
+                "(define f (lambda (n) (+ 1 (f n)))) (f 1)",
+                "(define g (lambda (n) (cond (else (let ((x (g n))) x))))) (g 1)",
+                "(define h (lambda (n) (cons n (letrec ((l (h n))) l)))) (h 1)",
+                "(define k (lambda (n) (begin (if (k n) 1 2)))) (k 1)",
+            ];
+            for source in sources.iter() {
+                let error = interpreter.eval_str(source).unwrap_err();
+                assert!(
+                    error.to_string().contains("recursion too deep"),
+                    "{}",
+                    error
+                );
+            }
+        })
+        .unwrap()
+        .join();
+    assert!(result.is_ok());
+
+    let mut interpreter = Interpreter::new();
+    let deep = "(define d (lambda (n) (if (= n 0) 0 (+ 1 (d (- n 1)))))) (d 100)";
+    assert_eq!(100, expect_number(interpreter.eval_str(deep)));
+    interpreter.set_recursion_limit(50);
+    assert!(interpreter.eval_str("(d 100)").is_err());
+    assert_eq!(20, expect_number(interpreter.eval_str("(d 20)")));
+}
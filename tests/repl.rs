@@ -0,0 +1,99 @@
+//! Tests for the interactive REPL in the `formula-one` binary
+
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+/// Run the REPL with `input` as its standard input
+fn run_repl(input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_formula-one"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("could not start the REPL");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn repl_reads_multi_line_forms() {
+    let output = run_repl("(define x\n  10)\n(+ x\n   \"a\n\")\n(+ x 1)\n");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains(" ~> 10\n"));
+    assert!(stdout.contains("error[E0002]: expected number, found string `a\n`"));
+    assert!(stdout.contains(" ~> 11\n"));
+    assert_eq!(3, stdout.matches("   .. ").count());
+}
+
+#[test]
+fn repl_exits_cleanly_at_end_of_input() {
+    let output = run_repl("");
+    assert!(output.status.success());
+
+    // Incomplete input is still reported before exiting
+    let output = run_repl("(+ 1");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("error[P0003]: expected `)`, found end of file"));
+}
+
+#[test]
+fn repl_survives_bad_input() {
+    let output = run_repl(")\n(/ 1 0)\n(car '())\n(undefined)\n(+ 1 2)\n");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("error[P0002]"));
+    assert!(stdout.contains("error[E0004]: division by zero"));
+    assert!(stdout.contains("error[E0002]: expected pair, found nil"));
+    assert!(stdout.contains("error[E0001]: undefined symbol `undefined`"));
+    assert!(stdout.ends_with(" ~> 3\n🏎  > \n"));
+}
+
+#[test]
+fn repl_runs_commands() {
+    let output = run_repl(
+        "(define answer 42)\n:env\n:tokens (+ 1)\n:ast foo\n:time (* 6 7)\n:reset\nanswer\n:bogus\n:quit\n(+ 1 2)\n",
+    );
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains(" answer = 42\n"));
+    assert!(stdout.contains(" + = <native +>\n"));
+    assert!(stdout.contains(" [1, 2) `(`\n [2, 3) symbol `+`\n [4, 5) number `1`\n"));
+    assert!(stdout.contains("Symbol(\n"));
+    assert!(stdout.contains(" ~> 42\n took "));
+    assert!(stdout.contains("error[E0001]: undefined symbol `answer`"));
+    assert!(stdout.contains(" !! unknown command `:bogus`"));
+    // Nothing after `:quit` is evaluated
+    assert!(!stdout.contains(" ~> 3"));
+}
+
+#[test]
+fn repl_loads_files() {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/forms.f1");
+    let output = run_repl(&format!(":load {0}\n:load {0}\n:load missing.f1\n", path));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(2, stdout.matches(" ~> 1337\n").count());
+    assert!(stdout.contains(" !! could not read `missing.f1`"));
+}
+
+#[test]
+fn repl_replaces_definitions() {
+    let output = run_repl("(define x 1)\n(define x 2)\n(+ x 1)\n");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(!stdout.contains("error"));
+    assert!(stdout.contains(" ~> 3\n"));
+}
+
+#[test]
+fn repl_survives_deep_recursion() {
+    let output = run_repl("(define f (lambda (n) (+ 1 (f n))))\n(f 1)\n(+ 1 2)\n");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("error[E0009]: recursion too deep"));
+    assert!(stdout.contains(" ~> 3\n"));
+}